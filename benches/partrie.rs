@@ -1,14 +1,14 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use rayon::prelude::*;
 
-use par_trie::ParTrie;
+use par_trie::{ByteTrie, Found, ParTrie};
 
 fn get_text() -> Vec<String> {
     use std::fs::File;
     use std::io::Read;
-    const DATA: &[&str] = &["data/1984.txt", "data/sun-rising.txt"];
+    const DATA: &[&str] = &["data/1984.txt", "data/sun-rise.txt"];
     let mut contents = String::new();
-    File::open(&DATA[1])
+    File::open(DATA[1])
         .unwrap()
         .read_to_string(&mut contents)
         .unwrap();
@@ -18,8 +18,19 @@ fn get_text() -> Vec<String> {
         .collect()
 }
 
+fn get_1984() -> Vec<String> {
+    use std::fs::File;
+    use std::io::Read;
+    let mut contents = String::new();
+    File::open("data/1984.txt")
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    contents.split_whitespace().map(|s| s.to_string()).collect()
+}
+
 fn make_trie(words: &[String]) -> ParTrie<char> {
-    let mut trie = ParTrie::new();
+    let trie = ParTrie::new();
     for w in words {
         trie.insert(w.chars());
     }
@@ -32,7 +43,7 @@ fn rayon_insert() {
     words.par_iter().for_each(|word| {
         t.insert(word.chars());
     });
-    words.par_iter().enumerate().for_each(|(i, word)| {
+    words.par_iter().for_each(|word| {
         let found = t.find(word.chars());
         println!("{:?}", found.as_collected());
         assert!(
@@ -58,6 +69,104 @@ fn trie_get(b: &mut Criterion) {
     });
 }
 
+fn trie_get_into(b: &mut Criterion) {
+    let words = get_text();
+    let trie = make_trie(&words);
+    let mut found = Found::new();
+    b.bench_function("trie get into", |b| {
+        b.iter(|| {
+            for w in words.iter() {
+                trie.find_into(w, &mut found);
+            }
+        })
+    });
+}
+
+fn trie_get_batch(b: &mut Criterion) {
+    let words = get_text();
+    let trie = make_trie(&words);
+    b.bench_function("trie get batch", |b| b.iter(|| trie.find_batch(&words)));
+}
+
+fn byte_trie_vs_par_trie(b: &mut Criterion) {
+    let words = get_text();
+    let bytes = ParTrie::new();
+    let byte_trie = ByteTrie::new();
+    for w in &words {
+        bytes.insert_bytes(w.as_bytes());
+        byte_trie.insert(w.as_bytes());
+    }
+
+    let mut group = b.benchmark_group("bytes");
+    group.bench_function("ParTrie<u8> insert", |b| {
+        b.iter(|| {
+            let trie = ParTrie::new();
+            for w in &words {
+                trie.insert_bytes(w.as_bytes());
+            }
+        })
+    });
+    group.bench_function("ByteTrie insert", |b| {
+        b.iter(|| {
+            let trie = ByteTrie::new();
+            for w in &words {
+                trie.insert(w.as_bytes());
+            }
+        })
+    });
+    group.bench_function("ParTrie<u8> contains", |b| {
+        b.iter(|| {
+            for w in &words {
+                bytes.contains_bytes(w.as_bytes());
+            }
+        })
+    });
+    group.bench_function("ByteTrie contains", |b| {
+        b.iter(|| {
+            for w in &words {
+                byte_trie.contains(w.as_bytes());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn compressed_vs_plain(b: &mut Criterion) {
+    let words = get_1984();
+    let plain = ParTrie::new();
+    let compressed = ParTrie::compressed();
+    for w in &words {
+        plain.insert(w);
+        compressed.insert(w);
+    }
+
+    let mut group = b.benchmark_group("1984");
+    group.sample_size(10);
+    group.bench_function("plain insert", |b| {
+        b.iter(|| {
+            let trie = ParTrie::new();
+            words.par_iter().for_each(|w| {
+                trie.insert(w);
+            });
+        })
+    });
+    group.bench_function("compressed insert", |b| {
+        b.iter(|| {
+            let trie = ParTrie::compressed();
+            words.par_iter().for_each(|w| {
+                trie.insert(w);
+            });
+        })
+    });
+    group.bench_function("plain contains", |b| {
+        b.iter(|| words.iter().filter(|w| plain.contains(*w)).count())
+    });
+    group.bench_function("compressed contains", |b| {
+        b.iter(|| words.iter().filter(|w| compressed.contains(*w)).count())
+    });
+    group.finish();
+}
+
 // fn trie_insert_remove(b: &mut Criterion) {
 //     let words = get_text();
 
@@ -75,5 +184,14 @@ fn simulate_use(b: &mut Criterion) {
     b.bench_function("simulated use", |b| b.iter(rayon_insert));
 }
 
-criterion_group!(benches, trie_insert, trie_get, simulate_use);
+criterion_group!(
+    benches,
+    trie_insert,
+    trie_get,
+    trie_get_into,
+    trie_get_batch,
+    byte_trie_vs_par_trie,
+    compressed_vs_plain,
+    simulate_use
+);
 criterion_main!(benches);
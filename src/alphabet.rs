@@ -0,0 +1,119 @@
+/// A fixed set of elements numbered `0..size()`.
+///
+/// A `ParTrie` made with `ParTrie::with_alphabet` gives each node one slot per
+/// element of the alphabet, so finding a child is a lookup by index instead
+/// of a scan. Keys holding an element outside of the alphabet are rejected.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::{Alphabet, ParTrie};
+///
+/// /// The vowels of ARPABET.
+/// struct Vowels;
+///
+/// const VOWELS: &[&str] = &["AA", "AE", "AH", "AO", "EH", "IH", "IY", "UH", "UW"];
+///
+/// impl Alphabet<&'static str> for Vowels {
+///     fn size(&self) -> usize {
+///         VOWELS.len()
+///     }
+///
+///     fn index(&self, val: &&'static str) -> Option<usize> {
+///         VOWELS.iter().position(|v| v == val)
+///     }
+///
+///     fn element(&self, idx: usize) -> &'static str {
+///         VOWELS[idx]
+///     }
+/// }
+///
+/// let trie = ParTrie::with_alphabet(Vowels);
+/// trie.insert(vec!["AE", "IY"]);
+/// assert!(trie.contains(vec!["AE", "IY"]));
+/// assert!(trie.try_insert(vec!["AE", "K"]).is_err());
+/// ```
+pub trait Alphabet<T>: Send + Sync {
+    /// The number of elements, every index is less than this.
+    fn size(&self) -> usize;
+
+    /// The index of `val`, `None` if it is not part of the alphabet.
+    fn index(&self, val: &T) -> Option<usize>;
+
+    /// The element at `idx`, `idx` is always less than `size()`.
+    fn element(&self, idx: usize) -> T;
+}
+
+/// The nucleotides `A`, `C`, `G` and `T`, upper case only.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dna;
+
+const NUCLEOTIDES: [u8; 4] = *b"ACGT";
+
+impl Alphabet<u8> for Dna {
+    fn size(&self) -> usize {
+        NUCLEOTIDES.len()
+    }
+
+    fn index(&self, val: &u8) -> Option<usize> {
+        NUCLEOTIDES.iter().position(|n| n == val)
+    }
+
+    fn element(&self, idx: usize) -> u8 {
+        NUCLEOTIDES[idx]
+    }
+}
+
+impl Alphabet<char> for Dna {
+    fn size(&self) -> usize {
+        NUCLEOTIDES.len()
+    }
+
+    fn index(&self, val: &char) -> Option<usize> {
+        NUCLEOTIDES.iter().position(|n| char::from(*n) == *val)
+    }
+
+    fn element(&self, idx: usize) -> char {
+        char::from(NUCLEOTIDES[idx])
+    }
+}
+
+/// The letters `a` through `z`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowerAscii;
+
+impl Alphabet<u8> for LowerAscii {
+    fn size(&self) -> usize {
+        26
+    }
+
+    fn index(&self, val: &u8) -> Option<usize> {
+        if val.is_ascii_lowercase() {
+            Some((val - b'a') as usize)
+        } else {
+            None
+        }
+    }
+
+    fn element(&self, idx: usize) -> u8 {
+        b'a' + idx as u8
+    }
+}
+
+impl Alphabet<char> for LowerAscii {
+    fn size(&self) -> usize {
+        26
+    }
+
+    fn index(&self, val: &char) -> Option<usize> {
+        if val.is_ascii_lowercase() {
+            Some(*val as usize - 'a' as usize)
+        } else {
+            None
+        }
+    }
+
+    fn element(&self, idx: usize) -> char {
+        char::from(b'a' + idx as u8)
+    }
+}
@@ -1,14 +1,12 @@
-use std::borrow::{Borrow, BorrowMut};
+// not used by the trie yet
+#![allow(dead_code)]
+
 use std::fmt;
-use std::marker::PhantomData;
-use std::mem::{self, MaybeUninit};
-use std::ptr::{self, NonNull};
-use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering::*};
-use std::sync::Condvar;
-use std::sync::Once;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
 
 use crossbeam::epoch::{self, Atomic, Guard, Owned, Pointer, Shared};
-use crossbeam_queue::{ArrayQueue, PopError, PushError, SegQueue};
+use crossbeam_queue::{PopError, SegQueue};
 
 enum QueState<T> {
     Main {
@@ -54,11 +52,11 @@ impl<T> QueState<T> {
     /// Push item at end of `RawParVec`.
     unsafe fn push(&self, val: T, g: &Guard) {
         match self {
-            Main { buff, start } => {
+            Main { buff, start: _ } => {
                 let que = buff.load(SeqCst, g).deref();
                 que.push(val)
             },
-            Second { buff, start } => buff.load(SeqCst, g).deref().push(val),
+            Second { buff, start: _ } => buff.load(SeqCst, g).deref().push(val),
         }
     }
 
@@ -72,7 +70,7 @@ impl<T> QueState<T> {
                     let o = other.deref();
                     let res = o.pop();
                     if o.is_empty() {
-                        start.compare_and_swap(false, true, SeqCst);
+                        let _ = start.compare_exchange(false, true, SeqCst, SeqCst);
                     }
                     res
                 }
@@ -84,7 +82,7 @@ impl<T> QueState<T> {
                     let o = other.deref();
                     let res = o.pop();
                     if o.is_empty() {
-                        start.compare_and_swap(false, true, SeqCst);
+                        let _ = start.compare_exchange(false, true, SeqCst, SeqCst);
                     }
                     res
                 }
@@ -98,7 +96,7 @@ impl<T> QueState<T> {
         g: &'g Guard,
     ) -> Result<Shared<'g, T>, PopError> {
         match self {
-            Main { buff, start } => match buff.load(SeqCst, g).deref().pop() {
+            Main { buff, start: _ } => match buff.load(SeqCst, g).deref().pop() {
                 Ok(item) => {
                     let shared = Owned::from(item).into_shared(g);
                     other
@@ -109,7 +107,7 @@ impl<T> QueState<T> {
                 }
                 Err(e) => Err(e),
             },
-            Second { buff, start } => match buff.load(SeqCst, g).deref().pop() {
+            Second { buff, start: _ } => match buff.load(SeqCst, g).deref().pop() {
                 Ok(item) => {
                     let shared = Owned::from(item).into_shared(g);
                     other
@@ -125,8 +123,8 @@ impl<T> QueState<T> {
 }
 
 pub struct RawParVec<T> {
-    primary_buff: SegQueue<T>,
-    second_buff: SegQueue<T>,
+    primary_buff: Box<SegQueue<T>>,
+    second_buff: Box<SegQueue<T>>,
     len: AtomicUsize,
     state: Atomic<QueState<T>>,
 }
@@ -134,9 +132,9 @@ pub struct RawParVec<T> {
 /// EXPENSIVE TO PRINT SELF
 impl<T: fmt::Debug> fmt::Debug for RawParVec<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let g = epoch::pin();
+        let _g = epoch::pin();
         let mut v = Vec::default();
-        for x in 0..self.len() {
+        for _x in 0..self.len() {
             v.push(self.primary_buff.pop().unwrap())
         }
 
@@ -160,11 +158,11 @@ impl<T: fmt::Debug> RawParVec<T> {
     ///
     unsafe fn new() -> RawParVec<T> {
         let len = AtomicUsize::new(0);
-        let primary_buff = SegQueue::new();
-        let second_buff = SegQueue::new();
+        let primary_buff = Box::new(SegQueue::new());
+        let second_buff = Box::new(SegQueue::new());
         let state = Atomic::from(QueState::new());
 
-        let mut que = Self {
+        let que = Self {
             primary_buff,
             second_buff,
             len,
@@ -172,7 +170,7 @@ impl<T: fmt::Debug> RawParVec<T> {
         };
 
         let buff: Atomic<SegQueue<T>> =
-            Atomic::from(Owned::from_usize(&que.primary_buff as *const SegQueue<T> as usize));
+            Atomic::from(Owned::from_usize(&*que.primary_buff as *const SegQueue<T> as usize));
         let state = Owned::from(QueState::Main {
             buff,
             start: AtomicBool::new(true),
@@ -193,30 +191,30 @@ impl<T: fmt::Debug> RawParVec<T> {
     unsafe fn push(&self, val: T, g: &Guard) {
         let state = unsafe { self.state.load(SeqCst, g).deref() };
         match state {
-            Main { buff, start } => {
+            Main { buff: _, start: _ } => {
                 state.push(val, g);
             },
-            Second { buff, start } => buff.load(SeqCst, g).deref().push(val),
+            Second { buff, start: _ } => buff.load(SeqCst, g).deref().push(val),
         }
     }
     /// Remove item from end of `RawParVec`.
     unsafe fn pop(&self, g: &Guard) -> Result<T, PopError> {
         let state = unsafe { self.state.load(SeqCst, g).deref() };
         match state {
-            Main { buff, start } => {
+            Main { buff: _, start: _ } => {
                 // buff.load(SeqCst, g).deref().pop()
-                let shared = Shared::from(&self.second_buff as *const _);
+                let shared = Shared::from(&*self.second_buff as *const _);
                 state.pop(shared, g)
             }
-            Second { buff, start } => buff.load(SeqCst, g).deref().pop(),
+            Second { buff, start: _ } => buff.load(SeqCst, g).deref().pop(),
         }
     }
 
     unsafe fn peek<'g>(&self, g: &'g Guard) -> Option<Shared<'g, T>> {
         let state = self.state.load(SeqCst, g).deref();
         match state {
-            Main { buff, start } => {
-                let second = Atomic::from(Owned::from_raw(&self.second_buff as *const _ as *mut _));
+            Main { buff: _, start } => {
+                let second = Atomic::from(Owned::from_raw(&*self.second_buff as *const _ as *mut _));
                 if let Ok(shared) = state.peek(second, g) {
                     start.swap(false, SeqCst);
                     println!("{:#?}", start.load(SeqCst));
@@ -224,7 +222,7 @@ impl<T: fmt::Debug> RawParVec<T> {
                 }
                 None
             }
-            Second { buff, start } => todo!("no QueueState::Second yet"),
+            Second { buff: _, start: _ } => todo!("no QueueState::Second yet"),
         }
     }
 }
@@ -239,7 +237,7 @@ unsafe impl<T> Sync for ParVec<T> {}
 
 impl<T: fmt::Debug> ParVec<T> {
     pub fn new() -> ParVec<T> {
-        let len = AtomicUsize::new(0);
+        let _len = AtomicUsize::new(0);
         let que = unsafe { RawParVec::new() };
         Self { que }
     }
@@ -255,14 +253,14 @@ impl<T: fmt::Debug> ParVec<T> {
     /// Push item at end of `RawParVec`.
     pub fn push(&self, val: T) {
         let g = epoch::pin();
-        let len = self.len();
+        let _len = self.len();
         unsafe { self.que.push(val, &g) }
     }
 
     /// Remove item from end of `RawParVec`.
     pub fn pop(&self) -> Result<T, PopError> {
         let g = epoch::pin();
-        let len = self.len();
+        let _len = self.len();
         unsafe { self.que.pop(&g) }
     }
 
@@ -310,7 +308,7 @@ mod tests {
 
     #[test]
     fn par_vec_guard() {
-        let g = epoch::pin();
+        let _g = epoch::pin();
         let guard: ParVec<usize> = ParVec::new();
 
         for x in 0..CONC_COUNT {
@@ -327,7 +325,7 @@ mod tests {
 
     #[test]
     fn par_vec_thread() {
-        let g = epoch::pin();
+        let _g = epoch::pin();
         let vec = ParVec::new();
 
         // std::thread::spawn(|| {
@@ -0,0 +1,330 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::node::{Children, Heap, Sizing};
+use crate::{hash_with, Alphabet, ByteTrie, Layout, ParTrie, RawTrie};
+
+/// Who copies a children array that has run out of room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizePolicy {
+    /// Every thread that runs into the full array makes a copy and the
+    /// first copy swapped in wins, nobody ever waits on anybody.
+    #[default]
+    Cooperative,
+    /// The first thread to run into the full array copies it while the
+    /// others wait for it to finish, no copy is made twice.
+    Single,
+}
+
+/// Caps on what a single key can do to a trie, `None` for no cap.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Limits {
+    pub(crate) key_len: Option<usize>,
+    pub(crate) fanout: Option<usize>,
+    pub(crate) nodes: Option<usize>,
+}
+
+/// Sets up how a `ParTrie` lays out and grows its nodes.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::{ParTrie, ResizePolicy};
+///
+/// let trie = ParTrie::builder()
+///     .root_capacity(64)
+///     .node_capacity(2)
+///     .growth_factor(1.5)
+///     .resize_policy(ResizePolicy::Single)
+///     .sorted()
+///     .build();
+///
+/// trie.insert("cow");
+/// trie.insert("cat");
+/// assert_eq!(trie.next_elements("c"), vec![('a', 1), ('o', 1)]);
+/// ```
+pub struct ParTrieBuilder<T> {
+    root_capacity: usize,
+    sizing: Sizing,
+    layout: Layout<T>,
+    compressed: bool,
+    burst: Option<usize>,
+    copy: Option<fn(&T) -> T>,
+    budget: Option<usize>,
+    limits: Limits,
+}
+
+impl<T> fmt::Debug for ParTrieBuilder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = match self.layout {
+            Layout::Linear => "linear",
+            Layout::Sorted { .. } => "sorted",
+            Layout::Alphabet(_) => "alphabet",
+            Layout::Hashed { .. } => "hashed",
+            Layout::Adaptive { .. } => "adaptive",
+        };
+        f.debug_struct("ParTrieBuilder")
+            .field("root_capacity", &self.root_capacity)
+            .field("node_capacity", &self.sizing.capacity)
+            .field("growth_factor", &self.sizing.growth)
+            .field("resize_policy", &self.sizing.resize)
+            .field("layout", &layout)
+            .field("compressed", &self.compressed)
+            .field("burst", &self.burst)
+            .field("memory_budget", &self.budget)
+            .field("max_key_len", &self.limits.key_len)
+            .field("max_fanout", &self.limits.fanout)
+            .field("max_nodes", &self.limits.nodes)
+            .finish()
+    }
+}
+
+impl<T> Default for ParTrieBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ParTrieBuilder<T> {
+    /// Linear nodes with room for 4 children to start with that double
+    /// in size whenever they fill up.
+    pub fn new() -> ParTrieBuilder<T> {
+        Self {
+            root_capacity: 0,
+            sizing: Sizing::default(),
+            layout: Layout::Linear,
+            compressed: false,
+            burst: None,
+            copy: None,
+            budget: None,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Room for this many first elements made up front, by default the
+    /// root starts out like any other node.
+    pub fn root_capacity(mut self, capacity: usize) -> Self {
+        self.root_capacity = capacity;
+        self
+    }
+
+    /// The number of children a node has room for when it gets its first.
+    pub fn node_capacity(mut self, capacity: usize) -> Self {
+        self.sizing.capacity = capacity;
+        self
+    }
+
+    /// How many times bigger a node's children array gets each time it
+    /// fills up, a hash table is then rounded up to a power of two.
+    ///
+    /// # Panics
+    ///
+    /// If `factor` is not above 1.
+    pub fn growth_factor(mut self, factor: f64) -> Self {
+        assert!(factor > 1.0, "a growth factor of {} would never grow", factor);
+        self.sizing.growth = factor;
+        self
+    }
+
+    /// Who copies a full children array, see `ResizePolicy`.
+    pub fn resize_policy(mut self, policy: ResizePolicy) -> Self {
+        self.sizing.resize = policy;
+        self
+    }
+
+    /// Children are kept in the order they were added and scanned, the
+    /// default.
+    pub fn linear(mut self) -> Self {
+        self.layout = Layout::Linear;
+        self
+    }
+
+    /// Children are kept in order and binary searched. Every add copies
+    /// the node's array so this suits tries that are read far more than
+    /// they are written, the capacity settings don't apply.
+    pub fn sorted(mut self) -> Self
+    where
+        T: Ord,
+    {
+        self.layout = Layout::Sorted { cmp: T::cmp };
+        self
+    }
+
+    /// Children are kept in open addressing hash tables, for nodes with
+    /// thousands of children.
+    pub fn hashed(mut self) -> Self
+    where
+        T: Hash,
+    {
+        self.layout = Layout::Hashed {
+            state: RandomState::new(),
+            hash: hash_with::<T>,
+        };
+        self
+    }
+
+    /// Children are scanned while a node's array is at most `limit` long,
+    /// once it has to grow past that it is turned into a hash table.
+    pub fn adaptive(mut self, limit: usize) -> Self
+    where
+        T: Hash,
+    {
+        self.layout = Layout::Adaptive {
+            state: RandomState::new(),
+            hash: hash_with::<T>,
+            limit,
+        };
+        self
+    }
+
+    /// Children are indexed by `alphabet`, see `ParTrie::with_alphabet`.
+    pub fn alphabet<A>(mut self, alphabet: A) -> Self
+    where
+        A: Alphabet<T> + 'static,
+    {
+        self.layout = Layout::Alphabet(Arc::new(alphabet));
+        self
+    }
+
+    /// Runs without branches are kept in one node, see `ParTrie::compressed`.
+    pub fn compressed(mut self) -> Self
+    where
+        T: Clone,
+    {
+        self.compressed = true;
+        self.copy = Some(T::clone);
+        self
+    }
+
+    /// Suffixes are kept in buckets of up to `threshold`, see `ParTrie::burst`.
+    ///
+    /// # Panics
+    ///
+    /// If `threshold` is 0.
+    pub fn burst(mut self, threshold: usize) -> Self
+    where
+        T: Clone,
+    {
+        assert!(threshold > 0, "a bucket has to hold at least one suffix");
+        self.burst = Some(threshold);
+        self.copy = Some(T::clone);
+        self
+    }
+
+    /// The most bytes the trie may allocate, see `ParTrie::heap_size`. An
+    /// insert that would take it over fails with `Error::OverBudget`, the
+    /// root array made for `root_capacity` is counted but never refused.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::{Error, ParTrie};
+    ///
+    /// let trie = ParTrie::builder().memory_budget(1 << 10).build();
+    /// let mut words = (0..).map(|i| format!("{:x}", i * 7919));
+    /// let err = loop {
+    ///     if let Err(err) = trie.try_insert(words.next().unwrap()) {
+    ///         break err;
+    ///     }
+    /// };
+    /// assert_eq!(err, Error::OverBudget { budget: 1 << 10 });
+    /// assert!(trie.heap_size() <= 1 << 10);
+    /// ```
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.budget = Some(bytes);
+        self
+    }
+
+    /// Keys longer than `max` elements are refused with `Error::KeyTooLong`
+    /// before anything is added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::{Error, ParTrie};
+    ///
+    /// let trie = ParTrie::builder().max_key_len(4).max_fanout(2).max_nodes(6).build();
+    /// assert_eq!(trie.try_insert("cattle"), Err(Error::KeyTooLong { max: 4 }));
+    /// assert!(trie.try_insert("cat").is_ok() && trie.try_insert("cow").is_ok());
+    /// assert_eq!(trie.try_insert("cut"), Err(Error::TooManyChildren { max: 2 }));
+    /// assert_eq!(trie.try_insert("dogs"), Err(Error::TooManyNodes { max: 6 }));
+    /// ```
+    pub fn max_key_len(mut self, max: usize) -> Self {
+        self.limits.key_len = Some(max);
+        self
+    }
+
+    /// A key that would give a node more than `max` children is refused
+    /// with `Error::TooManyChildren`. Inserts racing for the last free
+    /// spots of a node can each get one, so a node may end up with as
+    /// many more children as there were inserts running at once.
+    pub fn max_fanout(mut self, max: usize) -> Self {
+        self.limits.fanout = Some(max);
+        self
+    }
+
+    /// A key that would take the trie over `max` nodes is refused with
+    /// `Error::TooManyNodes`, the nodes it already added stay.
+    pub fn max_nodes(mut self, max: usize) -> Self {
+        self.limits.nodes = Some(max);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If the trie was asked to be both compressed and burst.
+    pub fn build(self) -> ParTrie<T> {
+        assert!(
+            !(self.compressed && self.burst.is_some()),
+            "a trie can be compressed or burst but not both"
+        );
+        let heap = Heap::new(self.budget);
+        let root = match &self.layout {
+            _ if self.root_capacity == 0 => Ok(Children::new()),
+            Layout::Linear => Children::with_capacity(self.root_capacity, false, &heap),
+            Layout::Hashed { .. } => Children::with_capacity(self.root_capacity, true, &heap),
+            Layout::Adaptive { limit, .. } => {
+                Children::with_capacity(self.root_capacity, self.root_capacity > *limit, &heap)
+            }
+            // sorted arrays are exactly as long as they are full and
+            // alphabets always have one slot per element
+            Layout::Sorted { .. } | Layout::Alphabet(_) => Ok(Children::new()),
+        };
+        // a root the allocator can't make up front grows as children are
+        // added like any other
+        let root = root.unwrap_or_else(|_| Children::new());
+        let raw = RawTrie {
+            root,
+            layout: self.layout,
+            sizing: self.sizing,
+            heap,
+            limits: self.limits,
+            compressed: self.compressed,
+            burst: self.burst,
+            copy: self.copy,
+            ..RawTrie::new()
+        };
+        ParTrie { raw }
+    }
+}
+
+impl ParTrieBuilder<u8> {
+    /// Builds a `ByteTrie` held to the memory budget and limits set on the
+    /// builder. Its nodes are always laid out the same way so the layout,
+    /// sizing and mode settings don't apply to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::{Error, ParTrie};
+    ///
+    /// let trie = ParTrie::builder().max_nodes(4).build_bytes();
+    /// assert!(trie.try_insert(b"GET").is_ok());
+    /// assert_eq!(trie.try_insert(b"PUT"), Err(Error::TooManyNodes { max: 4 }));
+    /// ```
+    pub fn build_bytes(self) -> ByteTrie {
+        ByteTrie::with_limits(self.budget, self.limits)
+    }
+}
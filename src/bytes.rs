@@ -0,0 +1,552 @@
+//! `ByteTrie`, a trie of byte strings with nodes of its own.
+//!
+//! Its nodes keep their children in arrays of their own rather than in
+//! `Children`, whose arrays take any layout and are sized by `Sizing`, as a
+//! byte node only ever grows once, from 16 packed children to 256 slots.
+//! Replacing an array goes through the same code though: `node::freeze`
+//! stops adds to the old one, `node::alloc_array` charges the trie's `Heap`
+//! for the copy and `node::replace_array` swaps it in and frees the old one
+//! through the epoch. Inserts are held to a memory budget and limits like a
+//! `ParTrie`'s, see `ParTrieBuilder::build_bytes`.
+//!
+//! There are no 16-way nibble nodes. They would split every byte over two
+//! levels, so two loads where the 256 slots need one, and the sparse nodes
+//! they would save memory on are the ones `Kind::Small` already covers with
+//! a single compare. The price is that a node with 17 children takes the
+//! same 256 slots as one with all of them.
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::*};
+
+use crossbeam_epoch::{self as epoch, Guard};
+
+use crate::builder::Limits;
+use crate::node::{self, Heap, FROZEN};
+use crate::pointers::{Atomic, Owned, Shared};
+use crate::{Error, Found};
+
+/// The most children a `Kind::Small` holds before it grows into a `Kind::Full`.
+const SMALL: usize = 16;
+
+/// The bytes a node takes up, not counting its children array.
+const NODE_BYTES: usize = mem::size_of::<ByteNode>();
+
+/// A trie of byte strings where finding a child never compares more than
+/// one node.
+///
+/// Nodes start out holding up to 16 children with their bytes packed into
+/// two words that are searched all at once (SSE2 on x86_64), past that
+/// they grow into 256 slots indexed directly by the byte. Growing uses the
+/// same freeze, copy and swap through the epoch that `ParTrie` children do
+/// so readers never wait on writers. `ParTrieBuilder::build_bytes` makes
+/// one with a memory budget and limits.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ByteTrie;
+///
+/// let trie = ByteTrie::new();
+/// trie.insert(b"GET /index");
+/// trie.insert(b"GET /about");
+///
+/// assert!(trie.contains(b"GET /about"));
+/// assert_eq!(trie.find(b"GET /").len(), 2);
+/// ```
+pub struct ByteTrie {
+    /// The root's byte is never looked at, its terminal flag is the empty key.
+    root: ByteNode,
+    heap: Heap,
+    limits: Limits,
+    /// The number of nodes, only kept up when there is a limit on it.
+    nodes: AtomicUsize,
+}
+
+impl fmt::Debug for ByteTrie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = epoch::pin();
+        f.debug_struct("ByteTrie")
+            .field("terminal", &self.root.is_terminal())
+            .field("children", &self.root.children(&g).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Default for ByteTrie {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteTrie {
+    pub fn new() -> ByteTrie {
+        Self::with_limits(None, Limits::default())
+    }
+
+    pub(crate) fn with_limits(budget: Option<usize>, limits: Limits) -> ByteTrie {
+        Self {
+            root: ByteNode::new(0),
+            heap: Heap::new(budget),
+            limits,
+            nodes: AtomicUsize::new(0),
+        }
+    }
+
+    /// True if no children hang off the root, the empty key may still be present.
+    pub fn is_empty(&self) -> bool {
+        self.root.children(&epoch::pin()).next().is_none()
+    }
+
+    /// The bytes allocated for the trie's nodes and their children arrays.
+    pub fn heap_size(&self) -> usize {
+        self.heap.used()
+    }
+
+    /// Inserts `key`, returns true if it was not already present.
+    ///
+    /// # Panics
+    ///
+    /// If `key` is over one of the trie's limits or its memory budget, use
+    /// `try_insert` to get an error instead.
+    pub fn insert(&self, key: &[u8]) -> bool {
+        self.try_insert(key).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Inserts `key`, returns true if it was not already present or an
+    /// error if the key was rejected. Like `ParTrie::try_insert` a key
+    /// refused for the node count or the budget leaves the nodes it already
+    /// added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::{Error, ParTrie};
+    ///
+    /// let trie = ParTrie::builder().max_key_len(8).max_fanout(2).build_bytes();
+    /// assert_eq!(trie.try_insert(b"GET /index"), Err(Error::KeyTooLong { max: 8 }));
+    /// assert!(trie.try_insert(b"GET /").is_ok() && trie.try_insert(b"PUT /").is_ok());
+    /// assert_eq!(trie.try_insert(b"DEL /"), Err(Error::TooManyChildren { max: 2 }));
+    /// ```
+    pub fn try_insert(&self, key: &[u8]) -> Result<bool, Error> {
+        if let Some(max) = self.limits.key_len {
+            if key.len() > max {
+                return Err(Error::KeyTooLong { max });
+            }
+        }
+        let g = epoch::pin();
+        let mut node = &self.root;
+        for b in key {
+            node = match node.child(*b, &g) {
+                Some(child) => child,
+                None => self.add_child(node, *b, &g)?,
+            };
+        }
+        Ok(node.set_terminal())
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let g = epoch::pin();
+        self.descend(key, &g).is_some_and(|node| node.is_terminal())
+    }
+
+    /// Returns every key that starts with `prefix`, `prefix` included.
+    pub fn find(&self, prefix: &[u8]) -> Found<u8> {
+        let g = epoch::pin();
+        let mut found = Found::new();
+        let node = match self.descend(prefix, &g) {
+            Some(node) => node,
+            None => return found,
+        };
+        found.temp.extend_from_slice(prefix);
+        if node.is_terminal() {
+            found.branch_end();
+        }
+
+        // depth first with a stack of our own, `None` steps back up a level
+        let mut stack = vec![];
+        push_children(node, &mut stack, &g);
+        while let Some(next) = stack.pop() {
+            match next {
+                Some(node) => {
+                    found.push_val(node.val);
+                    if node.is_terminal() {
+                        found.branch_end();
+                    }
+                    push_children(node, &mut stack, &g);
+                }
+                None => found.pop_val(),
+            }
+        }
+
+        fn push_children<'g>(node: &ByteNode, stack: &mut Vec<Option<&'g ByteNode>>, g: &'g Guard) {
+            // back to front so the first child comes off the stack first
+            let start = stack.len();
+            for n in node.children(g) {
+                stack.push(Some(n));
+                stack.push(None);
+            }
+            stack[start..].reverse();
+        }
+        found
+    }
+
+    fn descend<'g>(&'g self, key: &[u8], g: &'g Guard) -> Option<&'g ByteNode> {
+        let mut node = &self.root;
+        for b in key {
+            node = node.child(*b, g)?;
+        }
+        Some(node)
+    }
+
+    /// Adds a child for `b` to `node` held to the trie's limits and budget,
+    /// if another insert added it first theirs is returned.
+    fn add_child<'g>(&self, node: &'g ByteNode, b: u8, g: &'g Guard) -> Result<&'g ByteNode, Error> {
+        if let Some(max) = self.limits.fanout {
+            // racing inserts can pass the limit like they can in a `ParTrie`
+            if node.children(g).count() >= max {
+                return node.child(b, g).ok_or(Error::TooManyChildren { max });
+            }
+        }
+        if let Some(max) = self.limits.nodes {
+            self.nodes
+                .fetch_update(SeqCst, SeqCst, |n| Some(n + 1).filter(|&n| n <= max))
+                .map_err(|_| Error::TooManyNodes { max })?;
+        }
+        let new = node::alloc_array(NODE_BYTES, &self.heap, || Ok(ByteNode::new(b)))
+            .inspect_err(|_| self.free_node(0))?;
+        let ours: *const ByteNode = &*new;
+        let added = node.add_child(Owned::from(new), &self.heap, g);
+        match added {
+            Ok(added) if ptr::eq(added, ours) => {}
+            _ => self.free_node(NODE_BYTES),
+        }
+        added
+    }
+
+    /// Takes back what `add_child` counted for a node of `bytes` that never
+    /// made it into the trie.
+    fn free_node(&self, bytes: usize) {
+        self.heap.refund(bytes);
+        if self.limits.nodes.is_some() {
+            self.nodes.fetch_sub(1, SeqCst);
+        }
+    }
+}
+
+struct ByteNode {
+    val: u8,
+    terminal: AtomicBool,
+    /// Null until the first child is added.
+    kind: Atomic<Kind>,
+}
+
+enum Kind {
+    /// Filled front to back like `Children`, the byte of slot `i` is
+    /// byte `i % 8` of `keys[i / 8]` and is written right after the slot.
+    Small {
+        keys: [AtomicU64; 2],
+        nodes: [Atomic<ByteNode>; SMALL],
+    },
+    /// Slot `b` holds the child for byte `b`.
+    Full { nodes: Box<[Atomic<ByteNode>]> },
+}
+
+/// The bytes `kind` takes up, what the heap is charged for it.
+fn kind_bytes(kind: &Kind) -> usize {
+    let slots = match kind {
+        Kind::Small { .. } => 0,
+        Kind::Full { nodes } => nodes.len() * mem::size_of::<Atomic<ByteNode>>(),
+    };
+    mem::size_of::<Kind>() + slots
+}
+
+/// Only the bytes of the children are shown, a whole subtree could be too
+/// deep to print.
+impl fmt::Debug for ByteNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = epoch::pin();
+        f.debug_struct("ByteNode")
+            .field("value", &self.val)
+            .field("terminal", &self.is_terminal())
+            .field("children", &self.children(&g).map(|n| n.val).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Frees every node below, one level at a time from a stack of its own so
+/// a long key can't overflow the call stack.
+impl Drop for ByteNode {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_children(&mut stack);
+        while let Some(mut node) = stack.pop() {
+            node.take_children(&mut stack);
+        }
+    }
+}
+
+impl ByteNode {
+    fn new(val: u8) -> ByteNode {
+        Self {
+            val,
+            terminal: AtomicBool::default(),
+            kind: Atomic::null(),
+        }
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.terminal.load(SeqCst)
+    }
+
+    /// Moves the children onto `stack` and frees their array, the node is
+    /// left without children.
+    fn take_children(&mut self, stack: &mut Vec<Owned<ByteNode>>) {
+        // `&mut self` means no other thread can be looking
+        let g = unsafe { epoch::unprotected() };
+        let kind = self.kind.swap(Shared::null(), Relaxed, g);
+        if kind.is_null() {
+            return;
+        }
+        let kind = unsafe { kind.into_owned() };
+        let nodes: &[Atomic<ByteNode>] = match &*kind {
+            Kind::Small { nodes, .. } => nodes,
+            Kind::Full { nodes } => nodes,
+        };
+        for slot in nodes {
+            let node = slot.load(Relaxed, g).with_tag(0);
+            if !node.is_null() {
+                stack.push(unsafe { node.into_owned() });
+            }
+        }
+    }
+
+    fn set_terminal(&self) -> bool {
+        !self.terminal.swap(true, SeqCst)
+    }
+
+    fn children<'g>(&self, g: &'g Guard) -> impl Iterator<Item = &'g ByteNode> {
+        let nodes: &'g [Atomic<ByteNode>] = match unsafe { self.kind.load(SeqCst, g).as_ref() } {
+            Some(Kind::Small { nodes, .. }) => nodes,
+            Some(Kind::Full { nodes }) => nodes,
+            None => &[],
+        };
+        nodes
+            .iter()
+            .filter_map(move |n| unsafe { n.load(SeqCst, g).as_ref() })
+    }
+
+    fn child<'g>(&self, b: u8, g: &'g Guard) -> Option<&'g ByteNode> {
+        match unsafe { self.kind.load(SeqCst, g).as_ref()? } {
+            Kind::Small { keys, nodes } => {
+                let keys = [keys[0].load(SeqCst), keys[1].load(SeqCst)];
+                let mut matches = match_mask(keys, b);
+                // an unwritten key reads as 0 so every hit is checked against the node
+                while matches != 0 {
+                    let idx = matches.trailing_zeros() as usize;
+                    matches &= matches - 1;
+                    if let Some(node) = unsafe { nodes[idx].load(SeqCst, g).as_ref() } {
+                        if node.val == b {
+                            return Some(node);
+                        }
+                    }
+                }
+                None
+            }
+            Kind::Full { nodes } => unsafe { nodes[b as usize].load(SeqCst, g).as_ref() },
+        }
+    }
+
+    /// Adds `new` unless there already is a child for its byte, either way
+    /// the child that ends up in the node is returned. Arrays are charged
+    /// to `heap`.
+    fn add_child<'g>(&self, mut new: Owned<ByteNode>, heap: &Heap, g: &'g Guard) -> Result<&'g ByteNode, Error> {
+        let b = new.val;
+        loop {
+            let kind = self.kind.load(SeqCst, g);
+            match unsafe { kind.as_ref() } {
+                None => {
+                    let small = node::alloc_array(mem::size_of::<Kind>(), heap, || {
+                        Ok(Kind::Small {
+                            keys: Default::default(),
+                            nodes: Default::default(),
+                        })
+                    })?;
+                    node::replace_array(&self.kind, kind, small, kind_bytes, heap, g);
+                }
+                Some(Kind::Small { keys, nodes }) => {
+                    let mut idx = 0;
+                    while let Some(slot) = nodes.get(idx) {
+                        let current = slot.load(SeqCst, g);
+                        if current.tag() == FROZEN {
+                            break;
+                        }
+                        if current.is_null() {
+                            match slot.compare_and_set(Shared::null(), new, SeqCst, g) {
+                                Ok(added) => {
+                                    let shift = (idx % 8) * 8;
+                                    keys[idx / 8].fetch_or(u64::from(b) << shift, SeqCst);
+                                    return Ok(unsafe { added.deref() });
+                                }
+                                Err(err) => {
+                                    new = err.new;
+                                    continue;
+                                }
+                            }
+                        }
+                        let current = unsafe { current.deref() };
+                        if current.val == b {
+                            return Ok(current);
+                        }
+                        idx += 1;
+                    }
+                    self.grow(kind, nodes, heap, g)?;
+                }
+                Some(Kind::Full { nodes }) => {
+                    let slot = &nodes[b as usize];
+                    return match slot.compare_and_set(Shared::null(), new, SeqCst, g) {
+                        Ok(added) => Ok(unsafe { added.deref() }),
+                        Err(err) => Ok(unsafe { err.current.deref() }),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Freezes a full `Kind::Small`, copies its children into a
+    /// `Kind::Full` and swaps that in. If the copy can't be made the small
+    /// array stays frozen and the next add tries again.
+    fn grow<'g>(
+        &self,
+        old: Shared<'g, Kind>,
+        small: &'g [Atomic<ByteNode>],
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<(), Error> {
+        let live = node::freeze(small, g);
+        let bytes = mem::size_of::<Kind>() + 256 * mem::size_of::<Atomic<ByteNode>>();
+        let full = node::alloc_array(bytes, heap, || {
+            let mut nodes = node::try_vec(256)?;
+            nodes.resize_with(256, Atomic::null);
+            Ok(Kind::Full { nodes: nodes.into_boxed_slice() })
+        })?;
+        if let Kind::Full { nodes } = &*full {
+            for node in live {
+                // nobody else can see `full` yet so plain stores are enough
+                nodes[unsafe { node.deref() }.val as usize].store(node, SeqCst);
+            }
+        }
+        node::replace_array(&self.kind, old, full, kind_bytes, heap, g);
+        Ok(())
+    }
+}
+
+/// Bit `i` is set when byte `i` of the packed keys is `b`.
+#[cfg(target_arch = "x86_64")]
+fn match_mask(keys: [u64; 2], b: u8) -> u32 {
+    use std::arch::x86_64::{_mm_cmpeq_epi8, _mm_movemask_epi8, _mm_set1_epi8, _mm_set_epi64x};
+
+    // SSE2 is part of the x86_64 baseline
+    unsafe {
+        let keys = _mm_set_epi64x(keys[1] as i64, keys[0] as i64);
+        let hits = _mm_cmpeq_epi8(keys, _mm_set1_epi8(b as i8));
+        _mm_movemask_epi8(hits) as u32
+    }
+}
+
+/// Bit `i` is set when byte `i` of the packed keys is `b`.
+#[cfg(not(target_arch = "x86_64"))]
+fn match_mask(keys: [u64; 2], b: u8) -> u32 {
+    let mut mask = 0;
+    for (i, word) in keys.iter().enumerate() {
+        for (j, key) in word.to_le_bytes().iter().enumerate() {
+            if *key == b {
+                mask |= 1 << (i * 8 + j);
+            }
+        }
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn byte_trie_grows_full() {
+        let trie = ByteTrie::new();
+        assert!(trie.is_empty());
+        for b in 0..=255_u8 {
+            assert!(trie.insert(&[b, b]));
+        }
+        assert!(!trie.insert(&[0, 0]));
+        for b in 0..=255_u8 {
+            assert!(trie.contains(&[b, b]));
+            assert!(!trie.contains(&[b]));
+            assert_eq!(trie.find(&[b]).as_collected(), vec![&[b, b][..]]);
+        }
+        assert_eq!(trie.find(&[]).len(), 256);
+    }
+
+    /// What the heap should have been charged for everything reachable.
+    fn measured_heap_size(trie: &ByteTrie) -> usize {
+        let g = epoch::pin();
+        let kind = |node: &ByteNode| unsafe { node.kind.load(SeqCst, &g).as_ref() }.map_or(0, kind_bytes);
+        let mut bytes = kind(&trie.root);
+        let mut stack = trie.root.children(&g).collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            bytes += NODE_BYTES + kind(node);
+            stack.extend(node.children(&g));
+        }
+        bytes
+    }
+
+    #[test]
+    fn byte_trie_heap_and_limits() {
+        let trie = ByteTrie::new();
+        for b in 0..=255_u8 {
+            trie.insert(&[b, b, 1]);
+        }
+        assert_eq!(trie.heap_size(), measured_heap_size(&trie));
+
+        let trie = crate::ParTrie::builder().memory_budget(4 << 10).build_bytes();
+        let mut keys = (0_u32..).map(|i| i.wrapping_mul(2_654_435_761).to_le_bytes());
+        let err = loop {
+            if let Err(err) = trie.try_insert(&keys.next().unwrap()) {
+                break err;
+            }
+        };
+        assert_eq!(err, Error::OverBudget { budget: 4 << 10 });
+        assert!(trie.heap_size() <= 4 << 10);
+        assert_eq!(trie.heap_size(), measured_heap_size(&trie));
+
+        let trie = crate::ParTrie::builder().max_key_len(3).max_fanout(20).max_nodes(40).build_bytes();
+        assert_eq!(trie.try_insert(b"abcd"), Err(Error::KeyTooLong { max: 3 }));
+        for b in 0..20_u8 {
+            assert_eq!(trie.try_insert(&[b]), Ok(true));
+        }
+        assert_eq!(trie.try_insert(&[20]), Err(Error::TooManyChildren { max: 20 }));
+        assert_eq!(trie.try_insert(&[0]), Ok(false));
+        for b in 0..20_u8 {
+            assert_eq!(trie.try_insert(&[b, 0, 0]).is_ok(), b < 10);
+        }
+        assert_eq!(trie.try_insert(&[10, 0]), Err(Error::TooManyNodes { max: 40 }));
+        assert_eq!(trie.nodes.load(SeqCst), 40);
+        assert_eq!(trie.heap_size(), measured_heap_size(&trie));
+    }
+
+    #[test]
+    fn byte_trie_rayon_insert() {
+        let trie = ByteTrie::new();
+        let keys = (0..4096_u32).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        keys.par_iter().for_each(|key| {
+            trie.insert(key);
+        });
+        keys.par_iter().for_each(|key| {
+            assert!(trie.contains(key));
+        });
+        assert_eq!(trie.find(&[7]).len(), 16);
+    }
+}
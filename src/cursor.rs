@@ -0,0 +1,337 @@
+use std::fmt;
+
+use crossbeam_epoch::{self as epoch, Guard};
+
+use crate::node::Node;
+use crate::{Found, Level, RawTrie};
+
+/// A position inside of a `ParTrie` that can be moved one element at a time.
+///
+/// The cursor only pins the epoch for the length of each call, so keeping
+/// one around doesn't hold off freeing the memory other threads replace. It
+/// keeps the elements it walked and the nodes it found them in, and goes
+/// straight to the last of those nodes for as long as the trie's structure
+/// is unchanged. An edge split, a burst or a removal replaces the nodes it
+/// happens in, after one the elements are walked again from the root.
+/// Children are read as they are when asked so keys inserted below the
+/// cursor by other threads show up right away.
+///
+/// The walked elements are cloned, `TrieRef::cursor` makes a `CursorRef`
+/// that borrows them from the trie instead.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let trie = ParTrie::new();
+/// trie.insert("cat".chars());
+/// trie.insert("cow".chars());
+///
+/// let mut cursor = trie.cursor();
+/// assert!(cursor.push(&'c'));
+/// assert_eq!(cursor.next_elements().collect::<Vec<_>>(), vec!['a', 'o']);
+/// assert!(!cursor.push(&'x'));
+/// assert!(cursor.push(&'a') && cursor.push(&'t'));
+/// assert!(cursor.is_terminal());
+/// ```
+pub struct Cursor<'t, T> {
+    trie: &'t RawTrie<T>,
+    /// The elements walked from the root.
+    prefix: Vec<T>,
+    /// Where each element of `prefix` was found, cut short if it is no
+    /// longer in the trie.
+    path: Vec<Step<T>>,
+    /// The trie's version when `path` was walked, its nodes may only be
+    /// followed while the trie is still at it.
+    version: usize,
+}
+
+/// Where one element of a cursor's path was found, a `Level` without the
+/// borrow of the guard.
+enum Step<T> {
+    /// `at` elements into the tail of the node, 0 being the node's value.
+    Edge(*const Node<T>, usize),
+    /// `len` elements into the bucket of the node.
+    Suffix(*const Node<T>, usize),
+}
+
+impl<T> Step<T> {
+    /// Where `level` is, never the root.
+    fn at(level: Level<'_, T>) -> Step<T> {
+        match level {
+            Level::Node(node) | Level::Bucket(node, _, []) => Step::Edge(node, node.tail().len()),
+            Level::Inside(node, at) => Step::Edge(node, at),
+            Level::Bucket(node, _, prefix) => Step::Suffix(node, prefix.len()),
+            Level::Root(_) => unreachable!("a step always leaves the root"),
+        }
+    }
+}
+
+/// True if no node has been replaced since the trie was at `version`. Nodes
+/// found while it was are only freed once it moves on, so it has to be
+/// asked while pinned.
+fn is_current<T>(trie: &RawTrie<T>, version: usize) -> bool {
+    // odd while nodes are being replaced
+    trie.version() == version && version & 1 == 0
+}
+
+impl<'t, T: Eq + fmt::Debug> fmt::Debug for Cursor<'t, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cursor")
+            .field("prefix", &self.prefix)
+            .field("terminal", &self.is_terminal())
+            .finish()
+    }
+}
+
+impl<'t, T> Cursor<'t, T> {
+    /// The number of elements the cursor has walked down.
+    pub fn depth(&self) -> usize {
+        self.prefix.len()
+    }
+
+    /// Moves back up one element, returns false when already at the root.
+    pub fn pop(&mut self) -> bool {
+        self.path.truncate(self.prefix.len().saturating_sub(1));
+        self.prefix.pop().is_some()
+    }
+
+    /// The elements walked from the root to the cursor.
+    pub fn path(&self) -> impl Iterator<Item = &T> + '_ {
+        self.prefix.iter()
+    }
+}
+
+impl<'t, T: Eq> Cursor<'t, T> {
+    pub(crate) fn new(trie: &'t RawTrie<T>) -> Cursor<'t, T> {
+        Self {
+            trie,
+            prefix: Vec::new(),
+            path: Vec::new(),
+            version: trie.version(),
+        }
+    }
+
+    /// Where the cursor is, `None` if its elements are no longer a path in
+    /// the trie.
+    fn level<'g>(&'g self, g: &'g Guard) -> Option<Level<'g, T>> {
+        if !is_current(self.trie, self.version) || self.path.len() < self.prefix.len() {
+            let trie = self.trie;
+            return trie.descend(self.prefix.iter(), |c, val| trie.child(c, val, g), g);
+        }
+        Some(match self.path.last() {
+            None => Level::Root(self.trie),
+            Some(Step::Edge(node, at)) => Level::enter(unsafe { &**node }, *at, g),
+            Some(Step::Suffix(node, len)) => {
+                let node = unsafe { &**node };
+                Level::Bucket(node, node.suffixes(g)?, &self.prefix[self.prefix.len() - len..])
+            }
+        })
+    }
+
+    /// Walks the elements again from the root if a node on `path` was
+    /// replaced, `path` is left short if they are no longer in the trie.
+    fn refresh(&mut self, g: &Guard) {
+        if is_current(self.trie, self.version) && self.path.len() == self.prefix.len() {
+            return;
+        }
+        self.version = self.trie.version();
+        self.path.clear();
+        let trie = self.trie;
+        let mut child = |c, val: &T| trie.child(c, val, g);
+        let mut level = Level::Root(trie);
+        for val in &self.prefix {
+            level = match trie.step(level, val, &mut child, g) {
+                Some((level, _)) => level,
+                None => return,
+            };
+            self.path.push(Step::at(level));
+        }
+    }
+
+    /// True if the elements walked so far make up a complete key.
+    pub fn is_terminal(&self) -> bool {
+        let g = &epoch::pin();
+        self.level(g).is_some_and(|level| level.is_terminal(g))
+    }
+}
+
+impl<'t, T: Clone + Eq> Cursor<'t, T> {
+    /// Moves down to the child `val`, if there is no such child the cursor
+    /// stays where it is and false is returned.
+    pub fn push(&mut self, val: &T) -> bool {
+        let g = &epoch::pin();
+        self.refresh(g);
+        let trie = self.trie;
+        let mut child = |c, val: &T| trie.child(c, val, g);
+        let step = match self.level(g).and_then(|level| trie.step(level, val, &mut child, g)) {
+            Some((level, _)) => Step::at(level),
+            None => return false,
+        };
+        self.prefix.push(val.clone());
+        self.path.push(step);
+        true
+    }
+
+    /// The elements that `push` can currently move to.
+    pub fn next_elements(&self) -> impl Iterator<Item = T> {
+        let g = &epoch::pin();
+        let next = self.level(g).map_or(vec![], |level| level.next(g));
+        next.into_iter().map(|(val, _)| val.clone()).collect::<Vec<_>>().into_iter()
+    }
+
+    /// The elements walked from the root to the cursor.
+    pub fn prefix(&self) -> Vec<T> {
+        self.prefix.clone()
+    }
+
+    /// Every key at or below the cursor.
+    pub fn completions(&self) -> Found<T> {
+        let g = &epoch::pin();
+        let mut found = Found::new();
+        if let Some(level) = self.level(g) {
+            found.temp = self.prefix();
+            self.trie.searching_level(level, &mut found, g);
+        }
+        found
+    }
+}
+
+/// A `Cursor` that borrows the pin of a `TrieRef`, made by `TrieRef::cursor`.
+///
+/// The walked elements are borrowed from the trie so they don't need to be
+/// `Clone`. Like the handle it comes from it keeps the memory other threads
+/// replace from being freed for as long as it lives, it should not be kept
+/// around for longer than a batch.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let trie = ParTrie::<char>::from_str_list(&["cat", "cow"]);
+/// let pinned = trie.pin();
+/// let mut cursor = pinned.cursor();
+/// assert!(cursor.push(&'c'));
+/// assert_eq!(cursor.next_elements().collect::<Vec<_>>(), vec![&'a', &'o']);
+/// assert!(cursor.push(&'o') && cursor.push(&'w') && cursor.is_terminal());
+/// ```
+pub struct CursorRef<'r, T> {
+    trie: &'r RawTrie<T>,
+    guard: &'r Guard,
+    /// The elements walked from the root.
+    prefix: Vec<&'r T>,
+    /// Where each element of `prefix` was found, cut short if it is no
+    /// longer in the trie.
+    path: Vec<Level<'r, T>>,
+    /// The trie's version when `path` was walked.
+    version: usize,
+}
+
+impl<'r, T: Eq + fmt::Debug> fmt::Debug for CursorRef<'r, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CursorRef")
+            .field("prefix", &self.prefix)
+            .field("terminal", &self.is_terminal())
+            .finish()
+    }
+}
+
+impl<'r, T> CursorRef<'r, T> {
+    /// The number of elements the cursor has walked down.
+    pub fn depth(&self) -> usize {
+        self.prefix.len()
+    }
+
+    /// Moves back up one element, returns false when already at the root.
+    pub fn pop(&mut self) -> bool {
+        self.path.truncate(self.prefix.len().saturating_sub(1));
+        self.prefix.pop().is_some()
+    }
+
+    /// The elements walked from the root to the cursor, borrowed from the
+    /// trie.
+    pub fn path(&self) -> impl Iterator<Item = &'r T> + '_ {
+        self.prefix.iter().copied()
+    }
+}
+
+impl<'r, T: Eq> CursorRef<'r, T> {
+    pub(crate) fn new(trie: &'r RawTrie<T>, guard: &'r Guard) -> CursorRef<'r, T> {
+        Self {
+            trie,
+            guard,
+            prefix: Vec::new(),
+            path: Vec::new(),
+            version: trie.version(),
+        }
+    }
+
+    /// Where the cursor is, `None` if its elements are no longer a path in
+    /// the trie. Everything on `path` lives as long as the guard does, but
+    /// a node on it may have been replaced.
+    fn level(&self) -> Option<Level<'r, T>> {
+        if !is_current(self.trie, self.version) || self.path.len() < self.prefix.len() {
+            let (trie, g) = (self.trie, self.guard);
+            return trie.descend(self.prefix.iter().copied(), |c, val| trie.child(c, val, g), g);
+        }
+        Some(self.path.last().copied().unwrap_or(Level::Root(self.trie)))
+    }
+
+    /// Like `Cursor::refresh`.
+    fn refresh(&mut self) {
+        if is_current(self.trie, self.version) && self.path.len() == self.prefix.len() {
+            return;
+        }
+        self.version = self.trie.version();
+        self.path.clear();
+        let (trie, g) = (self.trie, self.guard);
+        let mut child = |c, val: &T| trie.child(c, val, g);
+        let mut level = Level::Root(trie);
+        for val in &self.prefix {
+            level = match trie.step(level, *val, &mut child, g) {
+                Some((level, _)) => level,
+                None => return,
+            };
+            self.path.push(level);
+        }
+    }
+
+    /// True if the elements walked so far make up a complete key.
+    pub fn is_terminal(&self) -> bool {
+        self.level().is_some_and(|level| level.is_terminal(self.guard))
+    }
+
+    /// Moves down to the child `val`, if there is no such child the cursor
+    /// stays where it is and false is returned.
+    pub fn push(&mut self, val: &T) -> bool {
+        self.refresh();
+        let (trie, g) = (self.trie, self.guard);
+        let mut child = |c, val: &T| trie.child(c, val, g);
+        match self.level().and_then(|level| trie.step(level, val, &mut child, g)) {
+            Some((level, matched)) => {
+                self.prefix.push(matched);
+                self.path.push(level);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The elements that `push` can currently move to.
+    pub fn next_elements(&self) -> impl Iterator<Item = &'r T> {
+        let next = self.level().map_or(vec![], |level| level.next(self.guard));
+        next.into_iter().map(|(val, _)| val)
+    }
+
+    /// Every key at or below the cursor.
+    pub fn completions(&self) -> Found<&'r T> {
+        let mut found = Found::new();
+        if let Some(level) = self.level() {
+            found.temp = self.prefix.clone();
+            self.trie.searching_level(level, &mut found, self.guard);
+        }
+        found
+    }
+}
@@ -0,0 +1,48 @@
+use std::error;
+use std::fmt;
+
+/// Why a key could not be added to a `ParTrie`, or keys moved within one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The element at `position` of the key is not part of the trie's alphabet.
+    NotInAlphabet { position: usize },
+    /// Adding the key would take the trie over its memory budget of
+    /// `budget` bytes.
+    OverBudget { budget: usize },
+    /// The allocator could not provide the memory for the key.
+    OutOfMemory,
+    /// The key is longer than the `max` elements the trie takes.
+    KeyTooLong { max: usize },
+    /// The key needs another child of a node that already has `max`.
+    TooManyChildren { max: usize },
+    /// The key needs more nodes than the `max` the trie may have.
+    TooManyNodes { max: usize },
+    /// Keys can't be moved to a prefix that already has keys below it.
+    DestinationNotEmpty,
+    /// Keys can't be moved to a prefix that starts with their own.
+    MoveIntoItself,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotInAlphabet { position } => {
+                write!(f, "element {} of the key is not in the alphabet", position)
+            }
+            Error::OverBudget { budget } => {
+                write!(f, "the key would take the trie over its budget of {} bytes", budget)
+            }
+            Error::OutOfMemory => write!(f, "out of memory"),
+            Error::KeyTooLong { max } => write!(f, "the key is longer than {} elements", max),
+            Error::TooManyChildren { max } => {
+                write!(f, "the key needs a node with more than {} children", max)
+            }
+            Error::TooManyNodes { max } => write!(f, "the key would take the trie over {} nodes", max),
+            Error::DestinationNotEmpty => write!(f, "the destination prefix already has keys"),
+            Error::MoveIntoItself => write!(f, "a prefix can't be moved below itself"),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,244 @@
+use std::ffi::OsString;
+use std::iter::{Cloned, Take};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Component, Components, Path, PathBuf};
+use std::str::{Bytes, Chars};
+use std::{array, slice, vec};
+
+/// Anything that can be broken down into the sequence of elements a
+/// `ParTrie<T>` stores.
+///
+/// Strings are sequences of `char`, byte slices and ip addresses are
+/// sequences of `u8` and paths are sequences of their components.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::{ParTrie, TrieKey};
+///
+/// struct Version(u32, u32, u32);
+///
+/// impl TrieKey<u32> for &Version {
+///     type Elements = std::array::IntoIter<u32, 3>;
+///
+///     fn elements(self) -> Self::Elements {
+///         IntoIterator::into_iter([self.0, self.1, self.2])
+///     }
+/// }
+///
+/// let trie = ParTrie::new();
+/// trie.insert(&Version(1, 4, 2));
+/// assert!(trie.contains(&Version(1, 4, 2)));
+/// assert!(!trie.contains(&Version(1, 4, 0)));
+/// ```
+pub trait TrieKey<T> {
+    type Elements: Iterator<Item = T>;
+
+    fn elements(self) -> Self::Elements;
+}
+
+impl<'a> TrieKey<char> for &'a str {
+    type Elements = Chars<'a>;
+
+    fn elements(self) -> Self::Elements {
+        self.chars()
+    }
+}
+
+impl<'a> TrieKey<char> for &'a String {
+    type Elements = Chars<'a>;
+
+    fn elements(self) -> Self::Elements {
+        self.chars()
+    }
+}
+
+impl TrieKey<char> for String {
+    type Elements = IntoChars;
+
+    fn elements(self) -> Self::Elements {
+        IntoChars { s: self, idx: 0 }
+    }
+}
+
+/// So the `insert(word.chars())` style keeps working.
+impl<'a> TrieKey<char> for Chars<'a> {
+    type Elements = Self;
+
+    fn elements(self) -> Self::Elements {
+        self
+    }
+}
+
+impl<'a> TrieKey<u8> for Bytes<'a> {
+    type Elements = Self;
+
+    fn elements(self) -> Self::Elements {
+        self
+    }
+}
+
+impl<'a, T: Clone> TrieKey<T> for &'a [T] {
+    type Elements = Cloned<slice::Iter<'a, T>>;
+
+    fn elements(self) -> Self::Elements {
+        self.iter().cloned()
+    }
+}
+
+impl<'a, T: Clone, const N: usize> TrieKey<T> for &'a [T; N] {
+    type Elements = Cloned<slice::Iter<'a, T>>;
+
+    fn elements(self) -> Self::Elements {
+        self.iter().cloned()
+    }
+}
+
+impl<T, const N: usize> TrieKey<T> for [T; N] {
+    type Elements = array::IntoIter<T, N>;
+
+    fn elements(self) -> Self::Elements {
+        IntoIterator::into_iter(self)
+    }
+}
+
+impl<'a, T: Clone> TrieKey<T> for &'a Vec<T> {
+    type Elements = Cloned<slice::Iter<'a, T>>;
+
+    fn elements(self) -> Self::Elements {
+        self.iter().cloned()
+    }
+}
+
+impl<T> TrieKey<T> for Vec<T> {
+    type Elements = vec::IntoIter<T>;
+
+    fn elements(self) -> Self::Elements {
+        self.into_iter()
+    }
+}
+
+impl<'a> TrieKey<OsString> for &'a Path {
+    type Elements = PathComponents<'a>;
+
+    fn elements(self) -> Self::Elements {
+        PathComponents { inner: self.components() }
+    }
+}
+
+impl<'a> TrieKey<OsString> for &'a PathBuf {
+    type Elements = PathComponents<'a>;
+
+    fn elements(self) -> Self::Elements {
+        self.as_path().elements()
+    }
+}
+
+impl TrieKey<OsString> for PathBuf {
+    type Elements = vec::IntoIter<OsString>;
+
+    fn elements(self) -> Self::Elements {
+        self.as_path().elements().collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl TrieKey<u8> for Ipv4Addr {
+    type Elements = array::IntoIter<u8, 4>;
+
+    fn elements(self) -> Self::Elements {
+        IntoIterator::into_iter(self.octets())
+    }
+}
+
+impl TrieKey<u8> for Ipv6Addr {
+    type Elements = array::IntoIter<u8, 16>;
+
+    fn elements(self) -> Self::Elements {
+        IntoIterator::into_iter(self.octets())
+    }
+}
+
+/// Version 4 addresses are their 4 octets and version 6 their 16, the same
+/// keys `Ipv4Addr` and `Ipv6Addr` give. There is no tag for the family so a
+/// version 4 key is a prefix of every version 6 key starting with the same
+/// octets: `find(10.0.0.1)` also finds `a00:1::1`. A trie that needs the two
+/// apart can hold `Ipv6Addr::to_ipv6_mapped` keys or one trie per family.
+impl TrieKey<u8> for IpAddr {
+    type Elements = Take<array::IntoIter<u8, 16>>;
+
+    fn elements(self) -> Self::Elements {
+        let mut octets = [0; 16];
+        let len = match self {
+            IpAddr::V4(ip) => {
+                octets[..4].copy_from_slice(&ip.octets());
+                4
+            }
+            IpAddr::V6(ip) => {
+                octets = ip.octets();
+                16
+            }
+        };
+        IntoIterator::into_iter(octets).take(len)
+    }
+}
+
+/// An element of a key on its way into the trie, borrowed elements are
+/// only cloned if a new node has to be made for them.
+pub(crate) trait Element<T> {
+    fn get(&self) -> &T;
+
+    fn into_owned(self) -> T;
+}
+
+impl<T> Element<T> for T {
+    fn get(&self) -> &T {
+        self
+    }
+
+    fn into_owned(self) -> T {
+        self
+    }
+}
+
+impl<T: Clone> Element<T> for &T {
+    fn get(&self) -> &T {
+        self
+    }
+
+    fn into_owned(self) -> T {
+        self.clone()
+    }
+}
+
+/// The chars of an owned `String`.
+#[derive(Debug, Clone)]
+pub struct IntoChars {
+    s: String,
+    idx: usize,
+}
+
+impl Iterator for IntoChars {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.s[self.idx..].chars().next()?;
+        self.idx += c.len_utf8();
+        Some(c)
+    }
+}
+
+/// The components of a `Path` as owned `OsString`s.
+#[derive(Debug, Clone)]
+pub struct PathComponents<'a> {
+    inner: Components<'a>,
+}
+
+impl<'a> Iterator for PathComponents<'a> {
+    type Item = OsString;
+
+    fn next(&mut self) -> Option<OsString> {
+        self.inner
+            .next()
+            .map(|c: Component<'_>| c.as_os_str().to_os_string())
+    }
+}
@@ -1,172 +1,271 @@
-use std::cell::UnsafeCell;
+use std::borrow::Borrow;
+use std::cmp;
+use std::collections::hash_map::RandomState;
 use std::fmt;
-use std::marker::PhantomData;
-use std::mem::{self, ManuallyDrop, MaybeUninit};
-use std::ops::{Deref, DerefMut};
+use std::hash::{BuildHasher, Hash};
 use std::ptr;
-use std::sync::{
-    atomic::{self, AtomicUsize, AtomicBool, Ordering::*},
-    Arc,
-    Condvar,
-    // Mutex,
-};
-
-// use crossbeam::epoch::{self, Atomic, Guard, Owned, Pointer, Shared};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+use std::sync::{Arc, PoisonError};
+
 use crossbeam_epoch::{self as epoch, Guard};
-use crossbeam_queue::{ArrayQueue, PopError, PushError, SegQueue};
-use parking_lot::Mutex;
+use crossbeam_utils::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
 
-mod pointers;
+mod alphabet;
 mod buffer;
+mod builder;
+mod bytes;
+mod cursor;
+mod error;
+mod key;
 mod node;
+#[cfg(feature = "rayon")]
+mod par;
+mod pinned;
+mod pointers;
+mod sets;
 // mod par_vec;
+mod strings;
 
-use pointers::{Atomic, Owned, Pointer, Shared};
-use node::Node;
+use key::Element;
+use node::{AddSuffix, Bucket, Children, Heap, Node, Place, Sizing};
+use pointers::{Owned, Shared};
+pub use alphabet::{Alphabet, Dna, LowerAscii};
+use builder::Limits;
+pub use builder::{ParTrieBuilder, ResizePolicy};
+pub use bytes::ByteTrie;
+pub use cursor::{Cursor, CursorRef};
+pub use pinned::TrieRef;
+pub use error::Error;
+pub use key::{IntoChars, PathComponents, TrieKey};
 // pub use par_vec::ParVec;
 
-struct RawTrie<T: fmt::Debug> {
-    root: Box<[Atomic<Node<T>>]>,
-    len: AtomicUsize,
-    resize_flag: AtomicBool,
-    lock: Mutex<()>,
+struct RawTrie<T> {
+    root: Children<T>,
+    /// Set when the empty sequence has been inserted.
+    terminal: AtomicBool,
+    layout: Layout<T>,
+    sizing: Sizing,
+    heap: Heap,
+    limits: Limits,
+    /// The number of nodes in the trie, only kept when they are limited.
+    nodes: AtomicUsize,
+    /// Nodes hold whole edges instead of single elements.
+    compressed: bool,
+    /// How many suffixes a node keeps in its bucket before it bursts,
+    /// `None` if suffixes always get nodes of their own.
+    burst: Option<usize>,
+    /// Copies elements out of keys and edges that are split. Compressed and
+    /// burst tries need it, so it is picked by the builder methods that
+    /// make them and only those put `Clone` on `T`.
+    copy: Option<fn(&T) -> T>,
+    /// Inserts hold this shared, changes that replace nodes hold it
+    /// exclusively. Readers never touch it. An insert only locks the shard
+    /// of its own thread so inserts don't contend on it.
+    structure: ShardedLock<()>,
+    /// Counts the times the structure lock was taken and let go of
+    /// exclusively, odd while it is held. Nodes are only replaced and
+    /// freed while it is, so a position found while it was even stays in
+    /// the trie for as long as it keeps that value.
+    version: AtomicUsize,
 }
 
-impl<T: fmt::Debug> fmt::Debug for RawTrie<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let g = epoch::pin();
-        let len = self.len.load(SeqCst);
+/// The structure lock held exclusively, the trie's version is odd for as
+/// long as this lives.
+struct Exclusive<'a> {
+    _lock: ShardedLockWriteGuard<'a, ()>,
+    version: &'a AtomicUsize,
+}
 
-        let mut v = Vec::default();
-        for x in self.root.iter() {
-            let node = x.load(SeqCst, &g);
-            if !node.is_null() {
-                v.push(unsafe { node.deref() });
-            }
+impl Drop for Exclusive<'_> {
+    fn drop(&mut self) {
+        self.version.fetch_add(1, SeqCst);
+    }
+}
+
+/// How every `Children` of a trie picks the slot for a child. The
+/// functions are picked when the trie is made so only the builder methods
+/// that need them put bounds on `T`.
+enum Layout<T> {
+    /// Children are scanned front to back.
+    Linear,
+    /// Children are kept ordered by `cmp`.
+    Sorted { cmp: fn(&T, &T) -> cmp::Ordering },
+    /// Slot `i` holds the element with alphabet index `i`.
+    Alphabet(Arc<dyn Alphabet<T>>),
+    /// Children live in open addressing tables.
+    Hashed {
+        state: RandomState,
+        hash: fn(&RandomState, &T) -> u64,
+    },
+    /// Children are scanned until there are more than `limit`, then hashed.
+    Adaptive {
+        state: RandomState,
+        hash: fn(&RandomState, &T) -> u64,
+        limit: usize,
+    },
+}
+
+/// The order to walk `keys` in so that keys with a common prefix come one
+/// after another, equal keys stay in the order they were given.
+fn batch_order<T: Ord>(keys: &[Vec<T>]) -> Vec<usize> {
+    let mut order = (0..keys.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+    order
+}
+
+impl<T> Clone for Layout<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Layout::Linear => Layout::Linear,
+            Layout::Sorted { cmp } => Layout::Sorted { cmp: *cmp },
+            Layout::Alphabet(alphabet) => Layout::Alphabet(Arc::clone(alphabet)),
+            Layout::Hashed { state, hash } => Layout::Hashed {
+                state: state.clone(),
+                hash: *hash,
+            },
+            Layout::Adaptive { state, hash, limit } => Layout::Adaptive {
+                state: state.clone(),
+                hash: *hash,
+                limit: *limit,
+            },
         }
+    }
+}
+
+fn hash_with<T: ?Sized + Hash>(state: &RandomState, val: &T) -> u64 {
+    state.hash_one(val)
+}
 
+impl<T: fmt::Debug> fmt::Debug for RawTrie<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Node")
-            .field("child_count", &len)
-            .field("children", &v)
+            .field("child_count", &self.root.len())
+            .field("children", &self.root)
             .finish()
     }
 }
 
-impl<T> RawTrie<T>
-where
-    T: Clone + PartialEq + Eq + fmt::Debug,
-{
-    /// TODO find a good number for init size
+impl<T> RawTrie<T> {
     fn new() -> RawTrie<T> {
         Self {
-            root: vec![Atomic::null(); 26 / 2].into_boxed_slice(),
-            len: AtomicUsize::new(0),
-            resize_flag: AtomicBool::default(),
-            lock: Mutex::new(()),
+            root: Children::new(),
+            terminal: AtomicBool::default(),
+            layout: Layout::Linear,
+            sizing: Sizing::default(),
+            heap: Heap::new(None),
+            limits: Limits::default(),
+            nodes: AtomicUsize::new(0),
+            compressed: false,
+            burst: None,
+            copy: None,
+            structure: ShardedLock::new(()),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// An empty trie laid out, sized and limited like this one.
+    fn empty_like(&self) -> RawTrie<T> {
+        Self {
+            layout: self.layout.clone(),
+            sizing: self.sizing,
+            heap: Heap::new(self.heap.budget()),
+            limits: self.limits,
+            compressed: self.compressed,
+            burst: self.burst,
+            copy: self.copy,
+            ..RawTrie::new()
         }
     }
 
     fn len(&self) -> usize {
-        self.len.load(SeqCst)
+        self.root.len()
+    }
+
+    /// The number of keys in the trie.
+    fn key_count(&self, g: &Guard) -> usize {
+        let empty = self.terminal.load(SeqCst) as usize;
+        empty + self.root.iter(g).map(Node::count).sum::<usize>()
     }
 
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
-    fn position(&self, key: &T, g: &Guard) -> Option<usize> {
-        self
-            .root
-            .iter()
-            .position(|node| unsafe {
-                let n = node.load(SeqCst, g);
-                if n.is_null() {
-                    false
-                } else {
-                    &n.deref().val == key
-                }
-            })
+    /// Holds off changes that replace nodes while inserting.
+    fn shared(&self) -> ShardedLockReadGuard<'_, ()> {
+        // nothing is left half done by a panic while it is held
+        self.structure.read().unwrap_or_else(PoisonError::into_inner)
     }
 
-    fn resize_check<'g>(&self, g: &'g Guard) {
-        let len = self.len();
-        // if we dont need to resize DON'T its expensive!
-        if len + 1 < self.root.len() {
-            return;
-        }
-        // TODO make this share load?
-        // this catches all other threads so this is one thread
-        if !self.resize_flag.load(SeqCst) {
-            println!("resize was false????");
-            return
-        }
-        
-        let new_len = self.root.len() * 2;
-        let mut root: Box<[Atomic<Node<T>>]> = vec![Atomic::null(); new_len].into_boxed_slice();
-
-        let mut new_count = 0;
-        for idx in 0..len {
-            let new = self.root[idx].load(SeqCst, g);
-            if root[idx]
-                .compare_and_set(Shared::null(), new, SeqCst, g)
-                .is_ok()
-            {
-                new_count += 1;
-            }
+    /// Waits for every insert to finish and holds off new ones, for changes
+    /// that replace nodes.
+    fn exclusive(&self) -> Exclusive<'_> {
+        let lock = self.structure.write().unwrap_or_else(PoisonError::into_inner);
+        self.version.fetch_add(1, SeqCst);
+        Exclusive {
+            _lock: lock,
+            version: &self.version,
         }
-        
-        // TODO SAFETY
-        // If we fence this very touchy cast concurrent use would be the fastest way to perdition
-        #[allow(clippy::cast_ref_to_mut)]
-        unsafe { mem::replace(&mut *(&self.root as *const _ as *mut _), root) };
-
-        // println!("{:#?}", self.root);
-        // println!("{:#?}", self.root.len());
+    }
 
-        assert!(self.len.compare_and_swap(len, new_count, SeqCst) == len);
-        // reset back to no resize
-        assert!(self.resize_flag.compare_and_swap(true, false, SeqCst));
+    /// The version of the trie's structure, see the `version` field.
+    fn version(&self) -> usize {
+        self.version.load(SeqCst)
     }
 
-    fn push_return<'g>(
-        &self,
-        val: Shared<'g, Node<T>>,
-        g: &'g Guard,
-    ) -> Result<Shared<'g, Node<T>>, Shared<'g, Node<T>>> {
-        loop {
-            match self.root
-                .get(self.len())
-                .map(|n| {
-                    match n.compare_and_set(Shared::null(), val, SeqCst, g) {
-                        Ok(_old) => {
-                            self.len.fetch_add(1, SeqCst);
-                            Ok(n.load(SeqCst, g))
-                        },
-                        Err(new) => Err(new.new),
-                    }
-                })
-            {
-                Some(n) => return n,
-                None => continue,
+    fn reserve(&self, additional: usize) -> Result<(), Error> {
+        let g = epoch::pin();
+        let _shared = self.shared();
+        let (sizing, heap) = (&self.sizing, &self.heap);
+        match &self.layout {
+            Layout::Linear => self.root.reserve(additional, sizing, heap, &g),
+            Layout::Hashed { state, hash } => {
+                self.root.reserve_hashed(additional, |v| hash(state, v), sizing, heap, &g)
+            }
+            Layout::Adaptive { state, hash, limit } => {
+                if self.len() + additional > *limit {
+                    self.root.reserve_hashed(additional, |v| hash(state, v), sizing, heap, &g)
+                } else {
+                    self.root.reserve(additional, sizing, heap, &g)
+                }
             }
+            // sorted arrays are only ever as long as they are full and
+            // alphabets always have one slot per element
+            Layout::Sorted { .. } | Layout::Alphabet(_) => Ok(()),
         }
     }
 
-    fn insert_rest(vals: &[T], node: Option<Shared<'_, Node<T>>>, mut idx: usize, g: &Guard) {
-        let mut node = node;
-        while let Some(next) = vals.get(idx) {
-            if let Some(prev) = node {
-                idx += 1;
-                let term = idx == vals.len();
-                node = unsafe {
-                    prev.deref()
-                        .add_child(Node::new(next.clone(), term), g)
-                };
-            }
+    /// Copies the children arrays of every node that have room to spare
+    /// into smaller ones, all of them or only those that are mostly empty.
+    /// The old arrays are freed once no reader is looking at them. Returns
+    /// the number of bytes freed.
+    fn compact(&self, all: bool) -> usize {
+        let g = epoch::pin();
+        // an edge split hands a node's array to its replacement, it must
+        // not be swapped out from under it
+        let _shared = self.shared();
+        let (sizing, heap) = (&self.sizing, &self.heap);
+        let mut freed = 0;
+        let mut stack = vec![&self.root];
+        while let Some(children) = stack.pop() {
+            freed += match &self.layout {
+                Layout::Linear => children.shrink(all, sizing, heap, &g),
+                Layout::Hashed { state, hash } | Layout::Adaptive { state, hash, .. } => {
+                    children.shrink_hashed(all, |v| hash(state, v), sizing, heap, &g)
+                }
+                Layout::Sorted { .. } | Layout::Alphabet(_) => 0,
+            };
+            stack.extend(children.iter(&g).map(Node::children));
         }
+        freed
     }
+}
 
-    /// inserts a sequence of T.
+impl<T: Eq> RawTrie<T> {
+    /// inserts a sequence of T, returns true if it was not already present.
+    ///
+    /// With an alphabet every element is checked before anything is added,
+    /// so a rejected key leaves no trace in the trie.
     ///
     /// # Examples
     ///
@@ -176,287 +275,1984 @@ where
     ///
     /// let guard = epoch::pin();
     /// let mut trie = RawTrie::new();
-    /// trie.insert_seq(&['c', 'a', 't'], &guard)
+    /// trie.insert_seq("cat".chars(), &guard)
     /// ```
-    fn insert_seq(&self, vals: &[T], g: &Guard) {
-        let len = self.len();
-        // if len is larger than capacity resize EXPENSIVE
-        if len + 1 >= self.root.len() {
-            if !self.resize_flag.compare_and_swap(false, true, SeqCst) {
-                self.resize_check(g);
-            } else {
-                while self.resize_flag.load(SeqCst) {
-                    atomic::spin_loop_hint()
-                }
+    fn insert_seq<I, E>(&self, vals: I, g: &Guard) -> Result<bool, Error>
+    where
+        I: Iterator<Item = E>,
+        E: Element<T>,
+    {
+        let checked = self.limits.key_len.is_some() || matches!(self.layout, Layout::Alphabet(_));
+        if !self.compressed && self.burst.is_none() && !checked {
+            return self.insert_nodes(vals, g);
+        }
+        let vals = match self.limits.key_len {
+            // stop reading as soon as the key is too long
+            Some(max) => vals.take(max.saturating_add(1)).collect::<Vec<_>>(),
+            None => vals.collect::<Vec<_>>(),
+        };
+        self.check_key(&vals)?;
+        if vals.is_empty() {
+            return Ok(!self.terminal.swap(true, SeqCst));
+        }
+        if self.compressed {
+            return self.insert_edges(&vals, g);
+        }
+        if let Some(capacity) = self.burst {
+            return self.insert_burst(&vals, capacity, g);
+        }
+        self.insert_nodes(vals.into_iter(), g)
+    }
+
+    /// Inserts into a plain trie a node at a time, straight from `vals`.
+    fn insert_nodes<I, E>(&self, vals: I, g: &Guard) -> Result<bool, Error>
+    where
+        I: Iterator<Item = E>,
+        E: Element<T>,
+    {
+        let _shared = self.shared();
+        let mut children = &self.root;
+        let mut path = Vec::with_capacity(vals.size_hint().0);
+        for val in vals {
+            // only allocate when the value is not already there
+            let node = match self.child(children, val.get(), g) {
+                Some(node) => node,
+                None => self.add_child(children, Node::new(val.into_owned()), g)?,
+            };
+            children = node.children();
+            path.push(node);
+        }
+        if path.is_empty() {
+            return Ok(!self.terminal.swap(true, SeqCst));
+        }
+        Ok(RawTrie::end_key(&path))
+    }
+
+    /// Refuses a key that is too long or has elements outside of the
+    /// trie's alphabet.
+    fn check_key<E: Element<T>>(&self, vals: &[E]) -> Result<(), Error> {
+        if let Some(max) = self.limits.key_len {
+            if vals.len() > max {
+                return Err(Error::KeyTooLong { max });
+            }
+        }
+        if let Layout::Alphabet(alphabet) = &self.layout {
+            if let Some(position) = vals.iter().position(|val| alphabet.index(val.get()).is_none()) {
+                return Err(Error::NotInAlphabet { position });
             }
         }
+        Ok(())
+    }
+
+    /// Inserts each of `keys` in turn, returning whether each was added.
+    /// In a plain trie a key starts from the deepest node it shares with
+    /// the key before it instead of from the root, so sorted keys walk
+    /// each shared prefix once.
+    fn insert_batch<I>(&self, keys: I, g: &Guard) -> Vec<Result<bool, Error>>
+    where
+        I: Iterator<Item = Vec<T>>,
+    {
+        if self.compressed || self.burst.is_some() {
+            // edges are split and buckets burst between keys, nodes on the
+            // last key's path may have been replaced
+            return keys.map(|key| self.insert_seq(key.into_iter(), g)).collect();
+        }
+
+        let _shared = self.shared();
+        let mut path: Vec<&Node<T>> = vec![];
+        keys.map(|key| {
+            self.check_key(&key)?;
+            if key.is_empty() {
+                return Ok(!self.terminal.swap(true, SeqCst));
+            }
+            let shared = path.iter().zip(&key).take_while(|(node, val)| node.val == **val).count();
+            path.truncate(shared);
+            for val in key.into_iter().skip(shared) {
+                let children = path.last().map_or(&self.root, |node| node.children());
+                let node = match self.child(children, &val, g) {
+                    Some(node) => node,
+                    None => self.add_child(children, Node::new(val), g)?,
+                };
+                path.push(node);
+            }
+            Ok(RawTrie::end_key(&path))
+        })
+        .collect()
+    }
 
-        if let Some(first) = vals.first() {
-            // already inserted start from node
-            if let Some(idx) = self.position(first, g) {
-                let _ = self.root.get(idx).map(|node| {
-                    let node = node.load(SeqCst, g);
-                    if node.is_null() {
-                        todo!("deal with null if another thread alters self.root")
+    /// Walks down to each of `keys` in turn, handing `each` the key's index
+    /// along with the level it ended at and the elements of the trie it
+    /// matched on the way, `None` if some element is missing. A key starts
+    /// from the deepest level it shares with the key before it instead of
+    /// from the root, so sorted keys walk each shared prefix once.
+    fn descend_batch<'g, F>(&'g self, keys: &[Vec<T>], order: &[usize], g: &'g Guard, mut each: F)
+    where
+        F: FnMut(usize, Option<Level<'g, T>>, &[&'g T]),
+    {
+        let mut child = |c, val: &T| self.child(c, val, g);
+        let mut levels = vec![Level::Root(self)];
+        let mut matched: Vec<&T> = vec![];
+        for &idx in order {
+            let key = &keys[idx];
+            let shared = matched.iter().zip(key).take_while(|(m, val)| **m == *val).count();
+            levels.truncate(shared + 1);
+            matched.truncate(shared);
+            for val in &key[shared..] {
+                let level = *levels.last().expect("the root is never popped");
+                match self.step(level, val, &mut child, g) {
+                    Some((level, m)) => {
+                        levels.push(level);
+                        matched.push(m);
                     }
-                    Self::insert_rest(vals, Some(node), 1, g);
-                });
-                return;
+                    None => break,
+                }
             }
-            // not already inserted start new branch at root no parent
-            let term = (len + 1) == vals.len();
-            // TODO deal with Err case SOON
-            let shared = Owned::from(Node::new(first.clone(), term)).into_shared(g);
-            let node = self.push_return(shared, g).ok();
-            Self::insert_rest(vals, node, 1, g);
+            let level = (matched.len() == key.len()).then(|| *levels.last().expect("the root is never popped"));
+            each(idx, level, &matched);
         }
     }
 
-    unsafe fn searching<'n>(node: Shared<'n, Node<T>>, key: &[T], found: &mut Found<T>, g: &Guard) {
-        let mut node = node;
-        // the calling function has already found the root node
-        let mut index = 1;
-
-        while let Some(key) = key.get(index) {
-            if node.is_null() {
-                todo!("null check in RawTrie::searching")
+    /// Like `find` for each of `keys` in the order given by `order`,
+    /// sharing the walk down to their common prefixes. The results are
+    /// indexed like `keys`.
+    fn find_batch<'g, E>(&'g self, keys: &[Vec<T>], order: &[usize], g: &'g Guard) -> Vec<Found<E>>
+    where
+        E: FoundElement<'g, T> + Clone,
+    {
+        let mut found = keys.iter().map(|_| Found::new()).collect::<Vec<_>>();
+        self.descend_batch(keys, order, g, |idx, level, matched| {
+            if let Some(level) = level {
+                let found = &mut found[idx];
+                found.temp.extend(matched.iter().map(|&val| E::from_trie(val)));
+                self.searching_level(level, found, g);
             }
-            let node_ref = node.deref();
-            if let Some(n) = node_ref.find_node(key, g) {
-                found.push_val(n.load(SeqCst, g).deref().to_value());
+        });
+        found
+    }
+
+    /// Inserts into a compressed trie, an edge that only partly matches the
+    /// key has to be split which waits for every other insert to finish.
+    fn insert_edges<E: Element<T>>(&self, key: &[E], g: &Guard) -> Result<bool, Error> {
+        let shared = self.shared();
+        if let Some(added) = self.walk_edges(key, false, g)? {
+            return Ok(added);
+        }
+        drop(shared);
+        let _exclusive = self.exclusive();
+        let added = self.walk_edges(key, true, g)?;
+        Ok(added.expect("edges can be split while holding the structure lock"))
+    }
+
+    /// Walks and extends the edges along `key`, `None` if an edge would have
+    /// to be split and `split` is false.
+    fn walk_edges<E: Element<T>>(&self, key: &[E], split: bool, g: &Guard) -> Result<Option<bool>, Error> {
+        let mut children = &self.root;
+        let mut path = Vec::new();
+        let mut idx = 0;
+        loop {
+            let rest = &key[idx + 1..];
+            let mut node = match self.child(children, key[idx].get(), g) {
+                Some(node) => node,
+                None => {
+                    // the tail is as long as the key, it is only allocated
+                    // if the trie has room for it
+                    self.heap.check(Node::<T>::size_with_tail(rest.len()))?;
+                    let copy = self.copier();
+                    let tail = node::try_tail(rest.iter().map(|val| copy(val.get())))?;
+                    // if another insert beat us to it we carry on down theirs
+                    self.add_child(children, Node::with_tail(copy(key[idx].get()), tail), g)?
+                }
+            };
 
-                index += 1;
-                node = n.load(SeqCst, g);
+            let matched = node
+                .tail()
+                .iter()
+                .zip(rest)
+                .take_while(|&(t, val)| t == val.get())
+                .count();
+            if matched < node.tail().len() {
+                if !split {
+                    return Ok(None);
+                }
+                node = self.split_edge(children, node, matched, g)?;
+            }
+            path.push(node);
+            idx += 1 + matched;
+            if idx == key.len() {
+                return Ok(Some(RawTrie::end_key(&path)));
             }
+            children = node.children();
         }
-        
-        if node.is_null() {
-            todo!("null check in RawTrie::searching")
+    }
+
+    /// Replaces `node` with a node for the first `at` elements of its tail
+    /// that has the rest of the edge as its only child.
+    fn split_edge<'g>(
+        &self,
+        children: &'g Children<T>,
+        node: &'g Node<T>,
+        at: usize,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Error> {
+        let copy = self.copier();
+        let tail = node::try_tail(node.tail()[..at].iter().map(copy))?;
+        let upper = self.alloc_node(Node::with_count(copy(&node.val), tail, node.count()))?;
+        let lower = node.split_off(at, copy, g).and_then(|lower| self.add_child(upper.children(), lower, g));
+        let lower = match lower {
+            Ok(lower) => lower,
+            Err(e) => {
+                self.free_node(upper.heap_size());
+                return Err(e);
+            }
+        };
+        // the replaced node's children now belong to the lower half
+        node.children().hand_over(lower.children(), g);
+        self.free_node(node.heap_size());
+        Ok(children.replace(node, upper, g))
+    }
+
+    /// Inserts into a burst trie, the key goes into the bucket of the first
+    /// node on its path that has not burst. A full bucket is burst while
+    /// every other insert waits and the insert starts over.
+    fn insert_burst<E: Element<T>>(&self, key: &[E], capacity: usize, g: &Guard) -> Result<bool, Error> {
+        loop {
+            let shared = self.shared();
+            let full = match self.walk_buckets(key, capacity, g)? {
+                Ok(added) => return Ok(added),
+                Err(full) => full,
+            };
+            drop(shared);
+            let _exclusive = self.exclusive();
+            self.burst_bucket(full, capacity, g)?;
         }
-        let node_ref = node.deref();
-        
-        recurse(node_ref, found, g);
+    }
 
-        fn recurse<T: Eq + fmt::Debug + Clone>(node: &Node<T>, found: &mut Found<T>, g: &Guard) {
-            // complete terminal branch no children
-            if node.is_terminal() && node.child_len() == 0 {
-                found.branch_end();
-                return;
-            // terminal but children after
-            } else if node.is_terminal() {
-                found.branch_end_continue();
+    /// Walks the nodes along `key` until a bucket takes the rest of it,
+    /// the node whose bucket is full is the inner error.
+    fn walk_buckets<'g, E: Element<T>>(
+        &'g self,
+        key: &[E],
+        capacity: usize,
+        g: &'g Guard,
+    ) -> Result<Result<bool, &'g Node<T>>, Error> {
+        let copy = self.copier();
+        let mut children = &self.root;
+        let mut path = Vec::new();
+        for (idx, val) in key.iter().enumerate() {
+            let node = match self.child(children, val.get(), g) {
+                Some(node) => node,
+                None => self.add_child(children, Node::new(copy(val.get())), g)?,
+            };
+            path.push(node);
+            let rest = &key[idx + 1..];
+            if rest.is_empty() {
+                break;
             }
-            // recurse iteratively over children
-            for n in node.children_iter(g) {
-                let n_ref = n.load(SeqCst, g);
-                if n_ref.is_null() {
-                    todo!("null check in recurse in RawTrie::find")
-                }
-                let n_ref = unsafe { n_ref.deref() };
-                found.push_val(n_ref.to_value());
-                
-                recurse(n_ref, found, g);
-                // not terminal but has more than one child, if deeper than single
-                // node we need some way of keeping track of what needs to be removed
-                // from temp vec
-                if !node.is_terminal() && node.child_len() > 1 {
-                    found.branch_split(node.as_value());
+            match node.add_suffix(rest, capacity, copy, &self.heap, g)? {
+                AddSuffix::Added => {
+                    path.iter().for_each(|n| n.count_key());
+                    return Ok(Ok(true));
                 }
+                AddSuffix::Present => return Ok(Ok(false)),
+                AddSuffix::Full => return Ok(Err(node)),
+                AddSuffix::Burst => children = node.children(),
             }
         }
+        Ok(Ok(RawTrie::end_key(&path)))
     }
 
-    /// Returns all of the found sequences, walking
-    /// each branch depth first.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use par_trie::RawTrie;
-    /// use crossbeam::epoch;
+    /// Moves the suffixes in the bucket of `node` into child nodes, each
+    /// with a bucket of its own for the rest of the suffix.
     ///
-    /// let guard = epoch::pin();
-    /// let mut trie = RawTrie::new();
-    /// 
-    /// trie.insert_seq(&['c', 'a', 't'], &guard);
-    /// trie.insert_seq(&['c', 'o', 'w'], &guard);
-    /// 
-    /// let found = trie.find(&['c'], &guard);
-    /// 
-    /// assert_eq!(
-    ///     found.as_collected().as_slice(),
-    ///     &[ ['c', 'a', 't'], ['c', 'o', 'w'] ]
-    /// );
-    /// ```
-    pub fn find<S: AsRef<[T]>>(&self, k: S, g: &Guard) -> Found<T> {
-        let keys = k.as_ref();
-        let mut found = Found::new();
-        if let Some(key) = keys.first() {
-            if let Some(idx) = self.position(key, g) {
-                let node = self.root[idx].load(SeqCst, g);
-                if node.is_null() {
-                    todo!("null check in find")
-                }
-                unsafe {
-                    // TODO will this catch single terminal vals
-                    found.push_val(node.deref().to_value());
-                    RawTrie::searching(node, keys, &mut found, g)
+    /// Readers keep using the bucket until it is ended so a burst that
+    /// fails half way is picked up again by the next one, every suffix is
+    /// only counted once it is newly in its child.
+    fn burst_bucket(&self, node: &Node<T>, capacity: usize, g: &Guard) -> Result<(), Error> {
+        // another insert may have burst it while we waited for the lock
+        if let Some(bucket) = node.suffixes(g) {
+            let copy = self.copier();
+            for suffix in bucket.iter(g) {
+                let child = match self.child(node.children(), &suffix[0], g) {
+                    Some(child) => child,
+                    None => self.add_child(node.children(), Node::new(copy(&suffix[0])), g)?,
+                };
+                let added = if suffix.len() == 1 {
+                    child.set_terminal()
+                } else {
+                    // a child never gets more suffixes than its parent had
+                    matches!(child.add_suffix(&suffix[1..], capacity, copy, &self.heap, g)?, AddSuffix::Added)
+                };
+                if added {
+                    child.count_key();
                 }
             }
+            node.end_bucket(&self.heap, g);
         }
-        found
+        Ok(())
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct Found<T> {
-    roll_back: Vec<usize>,
-    temp: Vec<T>,
-    collected: Vec<Vec<T>>,
-}
+    /// How elements are copied, only ever asked for by compressed and burst
+    /// tries.
+    fn copier(&self) -> fn(&T) -> T {
+        self.copy.expect("compressed and burst tries are built with a copy")
+    }
 
-impl<T: Clone + PartialEq> Found<T> {
-    fn new() -> Self {
-        Self {
-            roll_back: vec![],
-            temp: vec![],
-            collected: vec![],
+    /// Marks the last node of a newly walked key as terminal and counts the
+    /// key along its path, returns true if the key is new.
+    fn end_key(path: &[&Node<T>]) -> bool {
+        let node = path.last().expect("key is not empty");
+        let added = node.set_terminal();
+        if added {
+            path.iter().for_each(|n| n.count_key());
         }
+        added
     }
 
-    pub fn as_collected(&self) -> Vec<&[T]> {
-        self.collected
-            .iter()
-            .map(|seq| seq.as_slice())
-            .collect::<Vec<_>>()
+    /// The child of `children` holding `val`, looked up however the
+    /// trie's layout allows.
+    fn child<'g>(&self, children: &'g Children<T>, val: &T, g: &'g Guard) -> Option<&'g Node<T>> {
+        match &self.layout {
+            Layout::Linear => children.find(val, g),
+            Layout::Sorted { cmp } => children.find_sorted(val, *cmp, g),
+            Layout::Alphabet(alphabet) => children.get(alphabet.index(val)?, g),
+            Layout::Hashed { state, hash } => children.find_hashed(val, hash(state, val), g),
+            Layout::Adaptive { state, hash, .. } => children.find_adaptive(val, |v| hash(state, v), g),
+        }
     }
 
-    fn push_val(&mut self, t: T) {
-        self.temp.push(t);
+    /// Like `child` for any type `T` can be borrowed as. A hash table is
+    /// probed with the hash of `val`, which `Borrow` makes the same as that
+    /// of the element, other layouts are scanned.
+    fn child_borrowed<'g, Q>(&self, children: &'g Children<T>, val: &Q, g: &'g Guard) -> Option<&'g Node<T>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        match &self.layout {
+            Layout::Hashed { state, .. } => children.find_hashed(val, hash_with(state, val), g),
+            Layout::Adaptive { state, .. } => children.find_adaptive(val, |v| hash_with(state, v), g),
+            Layout::Linear | Layout::Sorted { .. } | Layout::Alphabet(_) => children.find(val, g),
+        }
     }
 
-    fn branch_end_continue(&mut self) {
-        self.collected.push(self.temp.clone());
+    /// How a children array is laid out in the trie's layout, for arrays
+    /// that are rebuilt instead of added to.
+    fn place(&self) -> Place<'_, T> {
+        match &self.layout {
+            Layout::Linear => Place::Dense(None),
+            Layout::Sorted { cmp } => Place::Dense(Some(*cmp)),
+            Layout::Alphabet(alphabet) => Place::Indexed(
+                Box::new(move |val| alphabet.index(val).expect("element is in the alphabet")),
+                alphabet.size(),
+            ),
+            Layout::Hashed { state, hash } => Place::Hashed(Box::new(move |val| hash(state, val))),
+            Layout::Adaptive { state, hash, .. } => Place::Adaptive(Box::new(move |val| hash(state, val))),
+        }
     }
 
-    fn branch_split(&mut self, key: &T)
-    where
-        T: std::fmt::Debug,
-    {
-        if let Some(idx) = self.temp.iter().position(|item| key == item) {
-            let (start, end) = self.temp.split_at(idx + 1);
-            self.temp = start.to_vec();
+    /// Counts `node` against the trie's limits, charges the heap for it and
+    /// moves it into the heap.
+    fn alloc_node(&self, node: Node<T>) -> Result<Owned<Node<T>>, Error> {
+        if let Some(max) = self.limits.nodes {
+            self.nodes
+                .fetch_update(SeqCst, SeqCst, |n| Some(n + 1).filter(|&n| n <= max))
+                .map_err(|_| Error::TooManyNodes { max })?;
+        }
+        let bytes = node.heap_size();
+        if let Err(e) = self.heap.charge(bytes) {
+            self.free_node(0);
+            return Err(e);
+        }
+        match node::try_box(node) {
+            Ok(node) => Ok(Owned::from(node)),
+            Err(e) => {
+                self.free_node(bytes);
+                Err(e)
+            }
         }
     }
 
-    fn branch_end(&mut self) {
-        self.collected.push(self.temp.clone());
-        // remove last element
-        self.temp.pop();
+    /// Counts and charges `node` like `alloc_node` but whatever the trie's
+    /// limits, for a node that takes the place of a larger one.
+    fn alloc_over(&self, node: Node<T>) -> Result<Owned<Node<T>>, Error> {
+        if self.limits.nodes.is_some() {
+            self.nodes.fetch_add(1, SeqCst);
+        }
+        let bytes = node.heap_size();
+        self.heap.charge_over(bytes);
+        node::try_box(node).map(Owned::from).inspect_err(|_| self.free_node(bytes))
     }
-}
 
-#[derive(Debug)]
-pub struct ParTrie<T: fmt::Debug> {
-    raw: RawTrie<T>,
-}
+    /// Takes back what `alloc_node` counted for a node of `bytes` that is
+    /// no longer, or never was, part of the trie.
+    fn free_node(&self, bytes: usize) {
+        self.heap.refund(bytes);
+        if self.limits.nodes.is_some() {
+            self.nodes.fetch_sub(1, SeqCst);
+        }
+    }
 
-impl<T> Default for ParTrie<T> 
-where
-    T: Clone + PartialEq + Eq + fmt::Debug,
-{
-    fn default() -> Self {
-        Self::new()
+    /// Adds `node` to `children` in the trie's layout, the alphabet has
+    /// already been checked. If another insert added the same value first
+    /// theirs is returned and `node` is dropped.
+    fn add_child<'g>(&self, children: &'g Children<T>, node: Node<T>, g: &'g Guard) -> Result<&'g Node<T>, Error> {
+        if let Some(max) = self.limits.fanout {
+            // racing inserts can't tell who got the last slot, so the limit
+            // can be passed by as many adds as are running at once
+            if children.len() >= max {
+                return self.child(children, &node.val, g).ok_or(Error::TooManyChildren { max });
+            }
+        }
+        let bytes = node.heap_size();
+        let node = self.alloc_node(node)?;
+        let ours: *const Node<T> = &*node;
+        let (sizing, heap) = (&self.sizing, &self.heap);
+        let added = match &self.layout {
+            Layout::Linear => children.add(node, sizing, heap, g),
+            Layout::Sorted { cmp } => children.add_sorted(node, *cmp, heap, g),
+            Layout::Alphabet(alphabet) => {
+                let idx = alphabet.index(&node.val).expect("element is in the alphabet");
+                children.add_at(idx, alphabet.size(), node, heap, g)
+            }
+            Layout::Hashed { state, hash } => children.add_hashed(node, |v| hash(state, v), sizing, heap, g),
+            Layout::Adaptive { state, hash, limit } => {
+                children.add_adaptive(node, |v| hash(state, v), *limit, sizing, heap, g)
+            }
+        };
+        match added {
+            Ok(added) if ptr::eq(added, ours) => {}
+            _ => self.free_node(bytes),
+        }
+        added
     }
-}
 
-impl<T> ParTrie<T>
-where
-    T: Clone + PartialEq + Eq + fmt::Debug,
-{
-    /// TODO find a good number for init size
-    pub fn new() -> ParTrie<T> {
-        Self { raw: RawTrie::new(), }
+    /// Walks `key` down from the root using `child` to find the node for the
+    /// first element of each edge, `None` if some element is missing.
+    fn descend<'g, Q, I, F>(&'g self, key: I, child: F, g: &'g Guard) -> Option<Level<'g, T>>
+    where
+        I: Iterator,
+        I::Item: Borrow<Q>,
+        T: Borrow<Q>,
+        Q: ?Sized + Eq,
+        F: FnMut(&'g Children<T>, &Q) -> Option<&'g Node<T>>,
+    {
+        self.descend_with(key, child, |_| (), g)
     }
 
-    // TODO make this more generic or make more helper func's
-    pub fn from_str_list(list: &[&str]) -> ParTrie<char> {
-        let this = ParTrie::new();
-        for word in list {
-            this.insert(word.chars())
-        } 
-        this
+    /// Like `descend` but hands each element of the trie to `each` as it
+    /// is matched.
+    fn descend_with<'g, Q, I, F, S>(&'g self, key: I, mut child: F, mut each: S, g: &'g Guard) -> Option<Level<'g, T>>
+    where
+        I: Iterator,
+        I::Item: Borrow<Q>,
+        T: Borrow<Q>,
+        Q: ?Sized + Eq,
+        F: FnMut(&'g Children<T>, &Q) -> Option<&'g Node<T>>,
+        S: FnMut(&'g T),
+    {
+        let mut level = Level::Root(self);
+        for val in key {
+            let (next, matched) = self.step(level, val.borrow(), &mut child, g)?;
+            each(matched);
+            level = next;
+        }
+        Some(level)
     }
 
-    pub fn len(&self) -> usize {
-        self.raw.len()
+    /// Moves one element down from `level`, whether that is along an edge,
+    /// into a bucket or to a child. Returns the new level and the element
+    /// of the trie that matched `val`.
+    fn step<'g, Q, F>(&'g self, level: Level<'g, T>, val: &Q, child: &mut F, g: &'g Guard) -> Option<(Level<'g, T>, &'g T)>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq,
+        F: FnMut(&'g Children<T>, &Q) -> Option<&'g Node<T>>,
+    {
+        match level {
+            Level::Inside(node, at) => {
+                let next = &node.tail()[at];
+                if next.borrow() == val {
+                    Some((Level::enter(node, at + 1, g), next))
+                } else {
+                    None
+                }
+            }
+            Level::Bucket(node, bucket, prefix) => {
+                let at = prefix.len();
+                let suffix = bucket.below(prefix, g).find(|s| s[at].borrow() == val)?;
+                Some((Level::Bucket(node, bucket, &suffix[..=at]), &suffix[at]))
+            }
+            _ => {
+                let node = child(level.children(), val)?;
+                Some((Level::enter(node, 0, g), &node.val))
+            }
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    fn contains<'g, Q, I, F>(&'g self, key: I, child: F, g: &'g Guard) -> bool
+    where
+        I: Iterator,
+        I::Item: Borrow<Q>,
+        T: Borrow<Q>,
+        Q: ?Sized + Eq,
+        F: FnMut(&'g Children<T>, &Q) -> Option<&'g Node<T>>,
+    {
+        self.descend(key, child, g).is_some_and(|level| level.is_terminal(g))
     }
 
-    pub fn insert<I: Iterator<Item=T>>(&self, iter: I) {
+    /// Every node in the trie, a compressed edge is one node.
+    #[cfg(test)]
+    fn node_count(&self) -> usize {
         let g = epoch::pin();
-        self.raw.insert_seq(&iter.into_iter().collect::<Vec<T>>(), &g)
+        let mut count = 0;
+        let mut stack = self.root.iter(&g).collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            count += 1;
+            stack.extend(node.children_iter(&g));
+        }
+        count
     }
-    pub fn find<I: Iterator<Item=T>>(&self, iter: I) -> Found<T> {
+
+    /// Adds up the memory of everything reachable from the root, which is
+    /// what the heap should have been charged for once inserts are done.
+    #[cfg(test)]
+    fn measured_heap_size(&self) -> usize {
         let g = epoch::pin();
-        self.raw.find(&iter.into_iter().collect::<Vec<T>>(), &g)
+        let below = self.root.iter(&g).map(|node| RawTrie::subtree_size(node, &g).0);
+        self.root.heap_size(&g) + below.sum::<usize>()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crossbeam_utils::thread;
-    use rayon::prelude::*;
-
-    const WORDS: &[&str; 20] = &[
-        "the", "them", "code", "coder", "coding",
-        "crap", "help", "heft", "apple", "hello",
-        "like", "love", "life", "huge", "copy",
-        "cookie", "zebra", "zappy", "king", "trie",
-    ];
-
-    fn get_text() -> Vec<String> {
-        use std::fs::File;
-        use std::io::Read;
-        const DATA: &[&str] = &["data/1984.txt", "data/sun-rise.txt"];
-        let mut contents = String::new();
-        File::open(&DATA[1])
-            .unwrap()
-            .read_to_string(&mut contents)
-            .unwrap();
-        contents
-            .split(|c: char| c.is_whitespace())
-            .map(|s| s.to_string())
-            .collect()
-    }
-    
-    fn make_trie(words: &[String]) -> ParTrie<char> {
-        let mut trie = ParTrie::new();
-        for w in words {
-            trie.insert(w.chars());
+    /// The bytes taken up by `node` and everything below it and the number
+    /// of nodes that makes.
+    fn subtree_size(node: &Node<T>, g: &Guard) -> (usize, usize) {
+        let (mut bytes, mut nodes) = (0, 0);
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            bytes += node.heap_size() + node.children().heap_size(g);
+            bytes += node.suffixes(g).map_or(0, |bucket| bucket.heap_size(g));
+            nodes += 1;
+            stack.extend(node.children_iter(g));
         }
-        trie
+        (bytes, nodes)
     }
 
-    #[test]
-    fn load_trie_3() {
-        let words = "cat cow car bob".split_whitespace().collect::<Vec<_>>();
-        let t = ParTrie::new();
-
-        for word in words {
-            println!("{:?}", word);
-            t.insert(word.chars());
+    /// Takes back what was counted for a subtree of `nodes` nodes taking up
+    /// `bytes` that is no longer part of the trie.
+    fn free_subtree(&self, (bytes, nodes): (usize, usize)) {
+        self.heap.refund(bytes);
+        if self.limits.nodes.is_some() {
+            self.nodes.fetch_sub(nodes, SeqCst);
         }
+    }
 
-        println!("{:#?}", t);
-        assert_eq!(t.len(), 2);
+    /// Unlinks every key that starts with `key` in one swap, so a reader
+    /// sees all of them or none, and returns how many keys went. Waits for
+    /// every insert to finish so none can land in the unlinked subtree.
+    ///
+    /// The topmost node left without keys is unlinked along with
+    /// everything below it. A prefix that ends part way into a bucket
+    /// leaves a copy of its node that keeps the bucket's other suffixes.
+    /// The empty prefix unlinks the root's whole array and then the empty
+    /// key, if there is one.
+    fn remove_prefix<I>(&self, key: I, g: &Guard) -> Result<usize, Error>
+    where
+        I: Iterator<Item = T>,
+        T: Clone,
+    {
+        let _exclusive = self.exclusive();
+        let (level, path) = match self.walk_path(key, g) {
+            Some(walk) => walk,
+            None => return Ok(0),
+        };
+        let node = match level {
+            Level::Root(_) => {
+                let removed = self.key_count(g);
+                self.unlink_root(g);
+                return Ok(removed);
+            }
+            Level::Node(node) | Level::Inside(node, _) | Level::Bucket(node, _, []) => node,
+            Level::Bucket(node, bucket, prefix) => {
+                return self.remove_suffixes(&path, node, bucket, |s| s.starts_with(prefix), g);
+            }
+        };
+        let removed = node.count();
+        self.unlink_keys(&path, removed, g)?;
+        Ok(removed)
     }
 
-    #[test]
+    /// Removes each of `keys` on its own, returns how many were in the
+    /// trie. Waits for every insert to finish like `remove_prefix`.
+    fn remove_keys<I>(&self, keys: I, g: &Guard) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = Vec<T>>,
+        T: Clone,
+    {
+        let _exclusive = self.exclusive();
+        let mut removed = 0;
+        for key in keys {
+            removed += self.remove_key(key.into_iter(), g)? as usize;
+        }
+        Ok(removed)
+    }
+
+    /// Removes `key` and nothing below it, returns whether it was there.
+    /// The topmost node left without keys is unlinked like in
+    /// `remove_prefix`, the node the key ends in otherwise stays and only
+    /// stops being the end of a key.
+    ///
+    /// Only while the structure lock is held exclusively.
+    fn remove_key<I>(&self, key: I, g: &Guard) -> Result<bool, Error>
+    where
+        I: Iterator<Item = T>,
+        T: Clone,
+    {
+        let (level, path) = match self.walk_path(key, g) {
+            Some(walk) => walk,
+            None => return Ok(false),
+        };
+        match level {
+            Level::Bucket(node, bucket, suffix) if !suffix.is_empty() => {
+                Ok(self.remove_suffixes(&path, node, bucket, |s| s == suffix, g)? > 0)
+            }
+            level => Ok(self.unset_key(&path, level, g)),
+        }
+    }
+
+    /// Stops `level` from being the end of a key, returns whether it was
+    /// one. `path` holds the nodes walked to get there, the topmost one
+    /// left without keys is unlinked like in `remove_key`.
+    ///
+    /// Only while the structure lock is held exclusively.
+    fn unset_key(&self, path: &[&Node<T>], level: Level<'_, T>, g: &Guard) -> bool {
+        match level {
+            Level::Root(_) => self.terminal.swap(false, SeqCst),
+            Level::Node(node) | Level::Bucket(node, _, []) => {
+                if !node.unset_terminal() {
+                    return false;
+                }
+                // the key is gone even if its empty branch can't be
+                // unlinked, it is then left in place holding no keys
+                if self.unlink_keys(path, 1, g).is_err() {
+                    path.iter().for_each(|n| n.uncount_keys(1));
+                }
+                true
+            }
+            // part way down an edge or a bucket is never the end of a key
+            Level::Inside(..) | Level::Bucket(..) => false,
+        }
+    }
+
+    /// Walks `key` down from the root, returning where it ends and every
+    /// node it goes through.
+    fn walk_path<'g, I>(&'g self, key: I, g: &'g Guard) -> Option<(Level<'g, T>, Vec<&'g Node<T>>)>
+    where
+        I: Iterator<Item = T>,
+    {
+        let mut child = |c, val: &T| self.child(c, val, g);
+        let mut level = Level::Root(self);
+        let mut path: Vec<&Node<T>> = vec![];
+        for val in key {
+            level = self.step(level, &val, &mut child, g)?.0;
+            let node = level.node().expect("a step always leaves the root");
+            if !path.last().is_some_and(|last| ptr::eq(*last, node)) {
+                path.push(node);
+            }
+        }
+        Some((level, path))
+    }
+
+    /// Takes `removed` keys off the count of every node of `path` and
+    /// unlinks the topmost one that has no keys left, along with everything
+    /// below it. The array it is in is swapped for a copy without it, made
+    /// whatever the trie's limits so that removing keys never fails on one.
+    ///
+    /// Only while the structure lock is held exclusively.
+    fn unlink_keys(&self, path: &[&Node<T>], removed: usize, g: &Guard) -> Result<(), Error> {
+        let mut above = path;
+        if let Some(at) = path.iter().position(|n| n.count() == removed) {
+            let (node, parent) = (path[at], self.parent(&path[..=at]));
+            let unbounded = Heap::new(None);
+            let rest = parent.rebuilt(&[node], &[], &self.place(), &unbounded, g)?;
+            self.heap.charge_over(unbounded.used());
+            let size = RawTrie::subtree_size(node, g);
+            parent.swap_array(rest, &self.heap, g);
+            // the node is in no array anymore, readers may still be in it
+            unsafe { pointers::defer_destroy(g, Shared::from(node as *const Node<T>)) };
+            self.free_subtree(size);
+            above = &path[..at];
+        }
+        above.iter().for_each(|n| n.uncount_keys(removed));
+        Ok(())
+    }
+
+    /// Unlinks the root's whole array in one swap and then the empty key,
+    /// returning the unlinked array and whether the empty key was in the
+    /// trie. The old tree can be walked for as long as `g` lives and is
+    /// freed once no reader can still be looking at it.
+    ///
+    /// Only while the structure lock is held exclusively.
+    fn unlink_root<'g>(&self, g: &'g Guard) -> (Option<&'g Children<T>>, bool) {
+        let below = self.root.iter(g).map(|node| RawTrie::subtree_size(node, g));
+        let size = below.fold((self.root.heap_size(g), 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        let unlinked = self.root.unlink_all(g);
+        self.free_subtree(size);
+        (unlinked, self.terminal.swap(false, SeqCst))
+    }
+
+    /// Empties the trie, waiting for every insert to finish like
+    /// `remove_prefix`.
+    fn clear(&self, g: &Guard) {
+        let _exclusive = self.exclusive();
+        self.unlink_root(g);
+    }
+
+    /// Empties the trie and pushes every key it held into `found`. The
+    /// keys are walked in the unlinked tree, so an insert that lands after
+    /// the swap is left in the trie and not pushed.
+    fn drain<'g, E>(&'g self, found: &mut Found<E>, g: &'g Guard)
+    where
+        E: FoundElement<'g, T> + Clone,
+    {
+        let (unlinked, empty) = {
+            let _exclusive = self.exclusive();
+            self.unlink_root(g)
+        };
+        found.clear();
+        if empty {
+            found.branch_end();
+        }
+        for node in unlinked.into_iter().flat_map(|c| c.iter(g)) {
+            found.push_edge(node);
+            RawTrie::searching(node, found, g);
+            found.pop_edge(node);
+        }
+    }
+
+    /// Moves every key that starts with `from` over to `to`, returns how
+    /// many keys moved.
+    ///
+    /// Below the longest prefix the two share the nodes along both paths
+    /// are copied bottom up, each copy holding the same children as the
+    /// original but for the one on the path. The node at the end of `to`
+    /// takes over the children of the one at the end of `from` and nothing
+    /// below it is copied. Both paths then go in with a single swap of the
+    /// children array of the node at the shared prefix.
+    fn rename_prefix(&self, from: &[T], to: &[T], g: &Guard) -> Result<usize, Error>
+    where
+        T: Clone,
+    {
+        self.check_key(to)?;
+        let _exclusive = self.exclusive();
+        let keys_below = |key: &[T]| {
+            let level = self.descend(key.iter(), |c, val| self.child(c, val, g), g);
+            level.map_or(0, |level| level.keys(g))
+        };
+        if keys_below(to) > 0 {
+            return Err(Error::DestinationNotEmpty);
+        }
+        let moved = keys_below(from);
+        if moved == 0 {
+            return Ok(0);
+        }
+        if to.starts_with(from) {
+            return Err(Error::MoveIntoItself);
+        }
+        if let Some(max) = self.limits.key_len {
+            let mut found: Found<&T> = Found::new();
+            self.find(from.iter(), &mut found, |c, val| self.child(c, val, g), g);
+            let longest = found.as_collected().iter().map(|key| key.len()).max().unwrap_or(0);
+            if to.len() + longest - from.len() > max {
+                return Err(Error::KeyTooLong { max });
+            }
+        }
+
+        let shared = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+        // the destination first, the edge the two paths part in is split
+        // at the shared prefix on the way
+        let to_path = self.walk_to_node(to, g)?;
+        let from_path = self.walk_to_node(from, g)?;
+        let above = from_path.iter().take_while(|(_, end)| *end <= shared).count();
+        let parent = match above {
+            0 => &self.root,
+            _ => from_path[above - 1].0.children(),
+        };
+        let (old, below_from) = from_path[above..].split_last().expect("`from` goes past the shared prefix");
+        let old = old.0;
+        // a node already at `to` has no keys below it and is replaced
+        let (copied_to, empty) = match &to_path[above..] {
+            [copied @ .., (node, end)] if *end == to.len() => (copied, Some(*node)),
+            copied => (copied, None),
+        };
+        let attach = copied_to.last().map_or(parent, |(node, _)| node.children());
+        if let Some(max) = self.limits.fanout {
+            let swapped = ptr::eq(attach, parent) && below_from.is_empty();
+            if empty.is_none() && !swapped && attach.len() >= max {
+                return Err(Error::TooManyChildren { max });
+            }
+        }
+
+        let place = self.place();
+        let mut fresh = vec![];
+        let mut copy_node = |node: Node<T>, terminal: bool| -> Result<Shared<'_, Node<T>>, Error> {
+            let node = self.alloc_node(node)?.into_shared(g);
+            fresh.push(node);
+            let copy = unsafe { node.deref() };
+            if terminal {
+                copy.set_terminal();
+            }
+            if self.burst.is_some() {
+                copy.end_bucket(&self.heap, g);
+            }
+            Ok(node)
+        };
+        let mut build = || -> Result<(Shared<'_, Node<T>>, Children<T>), Error> {
+            // the moved subtree under its new name, the rest of the edge in
+            // a compressed trie or a node for each element otherwise
+            let start = copied_to.last().map_or(shared, |(_, end)| *end);
+            let (edge, val) = (&to[start..], &to[to.len() - 1]);
+            let renamed = match self.compressed {
+                true => Node::with_count(edge[0].clone(), edge[1..].into(), moved),
+                false => Node::with_count(val.clone(), Box::new([]), moved),
+            };
+            let renamed = copy_node(renamed, old.is_terminal())?;
+            unsafe { renamed.deref() }.children().swap_array(old.children().share(g), &self.heap, g);
+            let mut new_to = renamed;
+            if !self.compressed {
+                for val in edge[..edge.len() - 1].iter().rev() {
+                    let node = copy_node(Node::with_count(val.clone(), Box::new([]), moved), false)?;
+                    let children = Children::new().rebuilt(&[], &[new_to], &place, &self.heap, g)?;
+                    unsafe { node.deref() }.children().swap_array(children, &self.heap, g);
+                    new_to = node;
+                }
+            }
+            let mut old_to = empty;
+            for &(node, _) in copied_to.iter().rev() {
+                let copy = Node::with_count(node.val.clone(), node.tail().into(), node.count() + moved);
+                let copy = copy_node(copy, node.is_terminal())?;
+                let children = node.children().rebuilt(old_to.as_slice(), &[new_to], &place, &self.heap, g)?;
+                unsafe { copy.deref() }.children().swap_array(children, &self.heap, g);
+                (old_to, new_to) = (Some(node), copy);
+            }
+
+            let (mut old_from, mut new_from) = (old, None);
+            for &(node, _) in below_from.iter().rev() {
+                let copy = Node::with_count(node.val.clone(), node.tail().into(), node.count() - moved);
+                let copy = copy_node(copy, node.is_terminal())?;
+                let children = node.children().rebuilt(&[old_from], new_from.as_slice(), &place, &self.heap, g)?;
+                unsafe { copy.deref() }.children().swap_array(children, &self.heap, g);
+                (old_from, new_from) = (node, Some(copy));
+            }
+
+            let gone = Some(old_from).into_iter().chain(old_to).collect::<Vec<_>>();
+            let added = new_from.into_iter().chain(Some(new_to)).collect::<Vec<_>>();
+            Ok((renamed, parent.rebuilt(&gone, &added, &place, &self.heap, g)?))
+        };
+        let (renamed, children) = match build() {
+            Ok(built) => built,
+            Err(e) => {
+                // every array first so dropping a copy frees nothing below it
+                for node in &fresh {
+                    unsafe { node.deref() }.children().release(&self.heap);
+                }
+                for node in fresh {
+                    let node = unsafe { node.into_owned() };
+                    self.free_node(node.heap_size());
+                }
+                return Err(e);
+            }
+        };
+
+        parent.swap_array(children, &self.heap, g);
+        old.children().hand_over(unsafe { renamed.deref() }.children(), g);
+        for &(node, _) in below_from.iter().chain(copied_to) {
+            node.children().retire(&self.heap, g);
+        }
+        for &(node, _) in below_from.iter().chain(copied_to).chain(Some(&(old, 0))) {
+            self.free_node(node.heap_size());
+            unsafe { pointers::defer_destroy(g, Shared::from(node as *const Node<T>)) };
+        }
+        if let Some(node) = empty {
+            self.free_subtree(RawTrie::subtree_size(node, g));
+            unsafe { pointers::defer_destroy(g, Shared::from(node as *const Node<T>)) };
+        }
+        Ok(moved)
+    }
+
+    /// Walks `key` down from the root as far as the trie goes, splitting
+    /// the edge it stops in and bursting every bucket on the way so that
+    /// it stops at the end of a node. Returns each node walked through
+    /// with the number of elements of `key` up to its end.
+    ///
+    /// Only while the structure lock is held exclusively.
+    fn walk_to_node<'g>(&'g self, key: &[T], g: &'g Guard) -> Result<Vec<(&'g Node<T>, usize)>, Error> {
+        let mut children = &self.root;
+        let mut path = vec![];
+        let mut idx = 0;
+        while idx < key.len() {
+            let mut node = match self.child(children, &key[idx], g) {
+                Some(node) => node,
+                None => break,
+            };
+            let matched = node.tail().iter().zip(&key[idx + 1..]).take_while(|(t, val)| t == val).count();
+            if matched < node.tail().len() {
+                node = self.split_edge(children, node, matched, g)?;
+            }
+            if let Some(capacity) = self.burst {
+                self.burst_bucket(node, capacity, g)?;
+            }
+            idx += 1 + matched;
+            path.push((node, idx));
+            children = node.children();
+        }
+        Ok(path)
+    }
+
+    /// Swaps `node` for a copy whose bucket holds only the suffixes `gone`
+    /// doesn't pick, or unlinks it if that leaves it without keys. Returns
+    /// how many suffixes `gone` picked.
+    fn remove_suffixes<F>(
+        &self,
+        path: &[&Node<T>],
+        node: &Node<T>,
+        bucket: &Bucket<T>,
+        gone: F,
+        g: &Guard,
+    ) -> Result<usize, Error>
+    where
+        F: Fn(&[T]) -> bool,
+        T: Clone,
+    {
+        let capacity = self.burst.expect("only burst tries have buckets");
+        let removed = bucket.iter(g).filter(|s| gone(s)).count();
+        if removed == 0 {
+            return Ok(0);
+        }
+        if node.count() == removed {
+            self.unlink_keys(path, removed, g)?;
+            return Ok(removed);
+        }
+        let copy = Node::with_count(node.val.clone(), node.tail().into(), node.count() - removed);
+        if node.is_terminal() {
+            copy.set_terminal();
+        }
+        // smaller than what it replaces, so it is made whatever the limits
+        let unbounded = Heap::new(None);
+        for suffix in bucket.iter(g).filter(|s| !gone(s)) {
+            copy.add_suffix(suffix, capacity, self.copier(), &unbounded, g)?;
+        }
+        self.heap.charge_over(unbounded.used());
+        let charged = unbounded.used();
+        let copy = self.alloc_over(copy).inspect_err(|_| self.heap.refund(charged))?;
+        let size = RawTrie::subtree_size(node, g);
+        self.parent(path).replace(node, copy, g);
+        self.free_subtree(size);
+        path.iter().for_each(|n| n.uncount_keys(removed));
+        Ok(removed)
+    }
+
+    /// The children holding the last node of `path`.
+    fn parent<'g>(&'g self, path: &[&'g Node<T>]) -> &'g Children<T> {
+        match path {
+            [.., parent, _] => parent.children(),
+            _ => &self.root,
+        }
+    }
+
+
+    /// The children directly below `key` along with the number of keys
+    /// under each of them.
+    fn next_elements<'g, I: Iterator<Item = T>>(&'g self, key: I, g: &'g Guard) -> Vec<(&'g T, usize)> {
+        match self.descend(key, |c, val| self.child(c, val, g), g) {
+            Some(level) => level.next(g),
+            None => vec![],
+        }
+    }
+
+    /// Pushes every sequence that ends at or below `node` into `found`,
+    /// `found` must already hold the values leading up to and including
+    /// the whole edge of `node`.
+    ///
+    /// Walks depth first with a stack of its own so no key is too long.
+    fn searching<'g, E>(node: &'g Node<T>, found: &mut Found<E>, g: &'g Guard)
+    where
+        E: FoundElement<'g, T> + Clone,
+    {
+        enum Visit<'n, T> {
+            Enter(&'n Node<T>),
+            Leave(&'n Node<T>),
+        }
+
+        let mut stack = vec![];
+        let mut next = Some(node);
+        loop {
+            let node = match next.take() {
+                Some(node) => node,
+                None => match stack.pop() {
+                    Some(Visit::Enter(node)) => {
+                        found.push_edge(node);
+                        node
+                    }
+                    Some(Visit::Leave(node)) => {
+                        found.pop_edge(node);
+                        continue;
+                    }
+                    None => return,
+                },
+            };
+            if node.is_terminal() {
+                found.branch_end();
+            }
+            if let Some(bucket) = node.suffixes(g) {
+                found.push_suffixes(bucket.iter(g));
+                continue;
+            }
+            // pushed back to front so the first child is entered first
+            let start = stack.len();
+            for n in node.children_iter(g) {
+                stack.push(Visit::Enter(n));
+                stack.push(Visit::Leave(n));
+            }
+            stack[start..].reverse();
+        }
+    }
+
+    /// Pushes every sequence in the trie into `found`.
+    fn searching_root<'g, E>(&'g self, found: &mut Found<E>, g: &'g Guard)
+    where
+        E: FoundElement<'g, T> + Clone,
+    {
+        if self.terminal.load(SeqCst) {
+            found.branch_end();
+        }
+        for node in self.root.iter(g) {
+            found.push_edge(node);
+            RawTrie::searching(node, found, g);
+            found.pop_edge(node);
+        }
+    }
+
+    /// Pushes every sequence at or below `level` into `found`, `found`
+    /// must already hold the values walked to get there.
+    fn searching_level<'g, E>(&'g self, level: Level<'g, T>, found: &mut Found<E>, g: &'g Guard)
+    where
+        E: FoundElement<'g, T> + Clone,
+    {
+        let terminal = level.is_terminal(g);
+        match level {
+            Level::Root(_) => self.searching_root(found, g),
+            Level::Node(node) => RawTrie::searching(node, found, g),
+            Level::Inside(node, at) => {
+                found.temp.extend(node.tail()[at..].iter().map(E::from_trie));
+                RawTrie::searching(node, found, g);
+            }
+            Level::Bucket(_, bucket, prefix) => {
+                if terminal {
+                    found.branch_end();
+                }
+                // `prefix` is already in `found`, only what comes after it is pushed
+                found.push_suffixes(bucket.below(prefix, g).map(|s| &s[prefix.len()..]));
+            }
+        }
+    }
+
+    /// Returns all of the found sequences, walking
+    /// each branch depth first.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use par_trie::RawTrie;
+    /// use crossbeam::epoch;
+    ///
+    /// let guard = epoch::pin();
+    /// let mut trie = RawTrie::new();
+    ///
+    /// trie.insert_seq("cat".chars(), &guard);
+    /// trie.insert_seq("cow".chars(), &guard);
+    ///
+    /// let mut found = Found::new();
+    /// trie.find("c".chars(), &mut found, |c, val| trie.child(c, val, &guard), &guard);
+    ///
+    /// assert_eq!(
+    ///     found.as_collected().as_slice(),
+    ///     &[ ['c', 'a', 't'], ['c', 'o', 'w'] ]
+    /// );
+    /// ```
+    pub fn find<'g, Q, I, F, E>(&'g self, key: I, found: &mut Found<E>, child: F, g: &'g Guard)
+    where
+        E: FoundElement<'g, T> + Clone,
+        I: Iterator,
+        I::Item: Borrow<Q>,
+        T: Borrow<Q>,
+        Q: ?Sized + Eq,
+        F: FnMut(&'g Children<T>, &Q) -> Option<&'g Node<T>>,
+    {
+        found.clear();
+        match self.descend_with(key, child, |val| found.push_val(E::from_trie(val)), g) {
+            Some(level) => self.searching_level(level, found, g),
+            None => found.clear(),
+        }
+    }
+}
+
+/// Where a walk down from the root stopped.
+enum Level<'g, T> {
+    Root(&'g RawTrie<T>),
+    /// At the end of the node's edge.
+    Node(&'g Node<T>),
+    /// Part way down a compressed edge, the next element is `tail[at]`.
+    Inside(&'g Node<T>, usize),
+    /// Somewhere in the bucket of a node that has not burst, among the
+    /// suffixes that start with the walked part of the bucket.
+    Bucket(&'g Node<T>, &'g Bucket<T>, &'g [T]),
+}
+
+impl<'g, T> Clone for Level<'g, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'g, T> Copy for Level<'g, T> {}
+
+impl<'g, T> Level<'g, T> {
+    /// The level after walking `at` elements of `node`'s tail.
+    fn enter(node: &'g Node<T>, at: usize, g: &'g Guard) -> Level<'g, T> {
+        if at < node.tail().len() {
+            return Level::Inside(node, at);
+        }
+        match node.suffixes(g) {
+            Some(bucket) => Level::Bucket(node, bucket, &[]),
+            None => Level::Node(node),
+        }
+    }
+
+    /// The node this level is in, `None` at the root.
+    fn node(&self) -> Option<&'g Node<T>> {
+        match self {
+            Level::Root(_) => None,
+            Level::Node(node) | Level::Inside(node, _) | Level::Bucket(node, ..) => Some(node),
+        }
+    }
+
+    /// The children below this level, only ever asked of the root or the
+    /// end of an edge without a bucket.
+    fn children(&self) -> &'g Children<T> {
+        match self {
+            Level::Root(trie) => &trie.root,
+            Level::Node(node) => node.children(),
+            Level::Inside(..) | Level::Bucket(..) => unreachable!("not the end of an edge"),
+        }
+    }
+
+    fn is_terminal(&self, g: &'g Guard) -> bool
+    where
+        T: PartialEq,
+    {
+        match self {
+            Level::Root(trie) => trie.terminal.load(SeqCst),
+            Level::Node(node) => node.is_terminal(),
+            Level::Inside(..) => false,
+            Level::Bucket(node, _, []) => node.is_terminal(),
+            Level::Bucket(_, bucket, prefix) => bucket.iter(g).any(|s| s == *prefix),
+        }
+    }
+
+    /// The number of keys at or below this level.
+    fn keys(&self, g: &'g Guard) -> usize
+    where
+        T: PartialEq,
+    {
+        match self {
+            Level::Root(trie) => trie.key_count(g),
+            Level::Node(node) | Level::Inside(node, _) | Level::Bucket(node, _, []) => node.count(),
+            Level::Bucket(_, bucket, prefix) => bucket.iter(g).filter(|s| s.starts_with(prefix)).count(),
+        }
+    }
+
+    /// The elements that can follow this level and how many keys go
+    /// through each.
+    fn next(&self, g: &'g Guard) -> Vec<(&'g T, usize)>
+    where
+        T: PartialEq,
+    {
+        match self {
+            Level::Root(_) | Level::Node(_) => self
+                .children()
+                .iter(g)
+                .map(|n| (&n.val, n.count()))
+                .collect(),
+            Level::Inside(node, at) => vec![(&node.tail()[*at], node.count())],
+            Level::Bucket(_, bucket, prefix) => {
+                let mut next: Vec<(&'g T, usize)> = vec![];
+                for suffix in bucket.below(prefix, g) {
+                    let val = &suffix[prefix.len()];
+                    match next.iter_mut().find(|(v, _)| *v == val) {
+                        Some((_, count)) => *count += 1,
+                        None => next.push((val, 1)),
+                    }
+                }
+                next
+            }
+        }
+    }
+}
+
+/// The keys found below a prefix.
+///
+/// A `Found` can be handed back to `ParTrie::find_into` to be refilled,
+/// which keeps the allocations from the last search around.
+#[derive(Debug, Clone)]
+pub struct Found<T> {
+    temp: Vec<T>,
+    collected: Vec<Vec<T>>,
+    /// Cleared sequences from earlier searches kept for their capacity.
+    spare: Vec<Vec<T>>,
+}
+
+impl<T> Default for Found<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Found<T> {
+    pub fn new() -> Self {
+        Self {
+            temp: vec![],
+            collected: vec![],
+            spare: vec![],
+        }
+    }
+
+    pub fn as_collected(&self) -> Vec<&[T]> {
+        self.collected
+            .iter()
+            .map(|seq| seq.as_slice())
+            .collect::<Vec<_>>()
+    }
+
+    /// The number of keys found.
+    pub fn len(&self) -> usize {
+        self.collected.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collected.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.temp.clear();
+        for mut seq in self.collected.drain(..) {
+            seq.clear();
+            self.spare.push(seq);
+        }
+    }
+}
+
+impl<E: Clone> Found<E> {
+    fn push_val(&mut self, e: E) {
+        self.temp.push(e);
+    }
+
+    fn pop_val(&mut self) {
+        self.temp.pop();
+    }
+
+    /// Pushes the whole edge of `node`, its value and then its tail.
+    fn push_edge<'g, T>(&mut self, node: &'g Node<T>)
+    where
+        E: FoundElement<'g, T>,
+    {
+        self.temp.push(E::from_trie(&node.val));
+        self.temp.extend(node.tail().iter().map(E::from_trie));
+    }
+
+    /// Ends a branch at each of `suffixes` pushed onto what is in `temp`.
+    fn push_suffixes<'g, T, I>(&mut self, suffixes: I)
+    where
+        I: Iterator<Item = &'g [T]>,
+        T: 'g,
+        E: FoundElement<'g, T>,
+    {
+        let len = self.temp.len();
+        for suffix in suffixes {
+            self.temp.extend(suffix.iter().map(E::from_trie));
+            self.branch_end();
+            self.temp.truncate(len);
+        }
+    }
+
+    fn pop_edge<T>(&mut self, node: &Node<T>) {
+        let len = self.temp.len() - 1 - node.tail().len();
+        self.temp.truncate(len);
+    }
+
+    fn branch_end(&mut self) {
+        let mut seq = self.spare.pop().unwrap_or_default();
+        seq.extend_from_slice(&self.temp);
+        self.collected.push(seq);
+    }
+}
+
+/// How a `Found` holds the elements of the keys it collects, cloned out of
+/// the trie or borrowed for as long as the guard they were found under.
+trait FoundElement<'g, T> {
+    fn from_trie(val: &'g T) -> Self;
+}
+
+impl<'g, T: Clone> FoundElement<'g, T> for T {
+    fn from_trie(val: &'g T) -> T {
+        val.clone()
+    }
+}
+
+impl<'g, T> FoundElement<'g, T> for &'g T {
+    fn from_trie(val: &'g T) -> &'g T {
+        val
+    }
+}
+
+impl<T> IntoIterator for Found<T> {
+    type Item = Vec<T>;
+    type IntoIter = std::vec::IntoIter<Vec<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.collected.into_iter()
+    }
+}
+
+pub struct ParTrie<T> {
+    raw: RawTrie<T>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for ParTrie<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParTrie").field("raw", &self.raw).finish()
+    }
+}
+
+impl<T> Default for ParTrie<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ParTrie<T> {
+    pub fn new() -> ParTrie<T> {
+        Self { raw: RawTrie::new(), }
+    }
+
+    /// Sets up the layout and sizing of the trie's nodes.
+    pub fn builder() -> ParTrieBuilder<T> {
+        ParTrieBuilder::new()
+    }
+
+    /// A trie whose root has room for `capacity` distinct first elements
+    /// before it has to grow.
+    pub fn with_capacity(capacity: usize) -> ParTrie<T> {
+        Self::builder().root_capacity(capacity).build()
+    }
+
+    /// A trie that only holds keys made of elements of `alphabet`, each
+    /// node indexes its children directly by the element's index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::{Dna, Error, ParTrie};
+    ///
+    /// let trie = ParTrie::with_alphabet(Dna);
+    /// assert!(trie.insert(b"GATTACA"));
+    /// assert_eq!(trie.try_insert(b"GATXACA"), Err(Error::NotInAlphabet { position: 3 }));
+    /// assert!(trie.find(b"GAT").len() == 1);
+    /// ```
+    pub fn with_alphabet<A>(alphabet: A) -> ParTrie<T>
+    where
+        A: Alphabet<T> + 'static,
+    {
+        Self::builder().alphabet(alphabet).build()
+    }
+
+    /// A radix trie, runs of elements without a branch are kept in a single
+    /// node instead of a node each. Queries behave exactly as they do on a
+    /// trie made with `new`, inserts that have to split an edge wait for
+    /// the inserts already running to finish.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::compressed();
+    /// trie.insert("coding");
+    /// trie.insert("cookie");
+    ///
+    /// assert!(trie.contains("coding") && !trie.contains("cod"));
+    /// assert_eq!(trie.next_elements("co"), vec![('d', 1), ('o', 1)]);
+    /// ```
+    pub fn compressed() -> ParTrie<T>
+    where
+        T: Clone,
+    {
+        Self::builder().compressed().build()
+    }
+
+    /// A burst trie, the suffixes below a node are kept together in a flat
+    /// bucket until there are more than `threshold` of them, then they
+    /// burst into child nodes. Queries behave exactly as they do on a trie
+    /// made with `new`, an insert that bursts a bucket waits for the
+    /// inserts already running to finish.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::burst(8);
+    /// trie.insert("the");
+    /// trie.insert("them");
+    /// trie.insert("theft");
+    ///
+    /// assert!(trie.contains("them") && !trie.contains("thef"));
+    /// assert_eq!(trie.next_elements("the"), vec![('m', 1), ('f', 1)]);
+    /// ```
+    pub fn burst(threshold: usize) -> ParTrie<T>
+    where
+        T: Clone,
+    {
+        Self::builder().burst(threshold).build()
+    }
+
+    /// A trie whose nodes keep their children in hash tables, for when a
+    /// node can have thousands of children like a trie of word tokens.
+    ///
+    /// Lookups through `contains_slice` and `find_slice` hash the borrowed
+    /// form and probe the table like owned ones do.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::hashed();
+    /// trie.insert("the quick brown fox".split(' ').map(String::from).collect::<Vec<_>>());
+    /// trie.insert("the lazy dog".split(' ').map(String::from).collect::<Vec<_>>());
+    ///
+    /// assert_eq!(trie.next_elements(vec!["the".to_string()]).len(), 2);
+    /// ```
+    pub fn hashed() -> ParTrie<T>
+    where
+        T: Hash,
+    {
+        Self::builder().hashed().build()
+    }
+
+    /// The number of distinct first elements, the children of the root.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Makes room for `additional` more distinct first elements so the
+    /// root does not have to grow while they are inserted. Does nothing
+    /// for sorted and alphabet layouts.
+    ///
+    /// # Panics
+    ///
+    /// If that would take the trie over its memory budget.
+    pub fn reserve(&self, additional: usize) {
+        self.raw.reserve(additional).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `reserve` but returns an error instead of panicking.
+    pub fn try_reserve(&self, additional: usize) -> Result<(), Error> {
+        self.raw.reserve(additional)
+    }
+
+    /// The bytes allocated for the trie's nodes, children arrays, buckets
+    /// and the elements they hold. Memory owned by the elements themselves,
+    /// like the contents of a `String`, is not counted.
+    pub fn heap_size(&self) -> usize {
+        self.raw.heap.used()
+    }
+
+    /// Copies every children array with room to spare into one just big
+    /// enough for the children it holds. Safe to call while other threads
+    /// read and insert, they keep using the old arrays until the new ones
+    /// are swapped in. Returns the number of bytes freed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::with_capacity(1000);
+    /// trie.insert("cat");
+    /// assert!(trie.shrink_to_fit() > 0);
+    /// assert_eq!(trie.shrink_to_fit(), 0);
+    /// assert!(trie.contains("cat"));
+    /// ```
+    pub fn shrink_to_fit(&self) -> usize {
+        self.raw.compact(true)
+    }
+
+    /// Like `shrink_to_fit` but only copies arrays that are mostly empty,
+    /// leaving room in the rest for the inserts still to come. Returns the
+    /// number of bytes freed.
+    pub fn compact(&self) -> usize {
+        self.raw.compact(false)
+    }
+}
+
+impl<T: Eq> ParTrie<T> {
+    /// Inserts `key`, returns true if it was not already present.
+    ///
+    /// # Panics
+    ///
+    /// If the trie has an alphabet and `key` holds an element outside of it,
+    /// use `try_insert` to get an error instead.
+    pub fn insert<K: TrieKey<T>>(&self, key: K) -> bool {
+        self.try_insert(key).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Inserts `key`, returns true if it was not already present or an
+    /// error if the key was rejected, in which case nothing is added.
+    pub fn try_insert<K: TrieKey<T>>(&self, key: K) -> Result<bool, Error> {
+        self.pin().try_insert(key)
+    }
+
+    /// Returns true if `key` was inserted, not just a prefix of some key.
+    pub fn contains<K: TrieKey<T>>(&self, key: K) -> bool {
+        self.pin().contains(key)
+    }
+
+    /// Inserts the key held in `key`, elements are only cloned when
+    /// there is no node for them yet.
+    ///
+    /// # Panics
+    ///
+    /// Like `insert` if an element is outside of the trie's alphabet.
+    pub fn insert_slice(&self, key: &[T]) -> bool
+    where
+        T: Clone,
+    {
+        let g = epoch::pin();
+        self.raw
+            .insert_seq(key.iter(), &g)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `contains` but for a slice of references to any type `T` can be
+    /// borrowed as, a `ParTrie<String>` can be asked about a `&[&str]`.
+    pub fn contains_slice<Q>(&self, key: &[&Q]) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.pin().contains_slice(key)
+    }
+
+    /// Inserts every one of `keys`, returning whether each was added in the
+    /// order the keys were given. See `TrieRef::insert_batch`.
+    ///
+    /// # Panics
+    ///
+    /// Like `insert`, once every other key is in.
+    pub fn insert_batch<K, I>(&self, keys: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        self.pin().insert_batch(keys)
+    }
+
+    /// Like `insert_batch` but returns an error for each rejected key
+    /// instead of panicking.
+    pub fn try_insert_batch<K, I>(&self, keys: I) -> Vec<Result<bool, Error>>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        self.pin().try_insert_batch(keys)
+    }
+
+    /// Whether each of `keys` was inserted, in the order the keys were
+    /// given. See `TrieRef::contains_batch`.
+    pub fn contains_batch<K, I>(&self, keys: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        self.pin().contains_batch(keys)
+    }
+
+    /// Removes every key. The root's array is swapped out in one go, a
+    /// reader sees either the whole old trie or an empty one, and the old
+    /// nodes are freed once no reader can still be looking at them. Waits
+    /// for the inserts already running to finish and holds off new ones
+    /// until the swap is done.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "cow"]);
+    /// trie.clear();
+    /// assert!(trie.is_empty() && !trie.contains("cat"));
+    /// assert_eq!(trie.heap_size(), 0);
+    /// ```
+    pub fn clear(&self) {
+        self.raw.clear(&epoch::pin())
+    }
+
+    /// Pins the current thread's epoch for a batch of operations, the
+    /// handle's lookups borrow elements from the trie instead of cloning
+    /// them. See `TrieRef`.
+    pub fn pin(&self) -> TrieRef<'_, T> {
+        TrieRef::new(&self.raw)
+    }
+}
+
+impl<T: Clone + Eq> ParTrie<T> {
+    /// Returns a `Cursor` positioned at the root, each `Cursor::push`
+    /// walks one element further down without starting over from the root.
+    pub fn cursor(&self) -> Cursor<'_, T> {
+        Cursor::new(&self.raw)
+    }
+
+    /// Returns every key that starts with `prefix`, `prefix` included.
+    pub fn find<K: TrieKey<T>>(&self, prefix: K) -> Found<T> {
+        let mut found = Found::new();
+        self.find_into(prefix, &mut found);
+        found
+    }
+
+    /// Like `find` but for a slice of references to any type `T` can be
+    /// borrowed as.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::new();
+    /// trie.insert_slice(&["the".to_string(), "cat".to_string()]);
+    /// trie.insert_slice(&["the".to_string(), "dog".to_string()]);
+    ///
+    /// assert_eq!(trie.find_slice(&["the"]).len(), 2);
+    /// ```
+    pub fn find_slice<Q>(&self, prefix: &[&Q]) -> Found<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        let g = epoch::pin();
+        let mut found = Found::new();
+        self.raw.find(prefix.iter().copied(), &mut found, |c, val| self.raw.child_borrowed(c, val, &g), &g);
+        found
+    }
+
+    /// Like `find` but fills `found`, reusing what it allocated for
+    /// earlier searches. Whatever `found` held before is cleared.
+    pub fn find_into<K: TrieKey<T>>(&self, prefix: K, found: &mut Found<T>) {
+        let g = epoch::pin();
+        self.raw.find(prefix.elements(), found, |c, val| self.raw.child(c, val, &g), &g)
+    }
+
+    /// A new trie holding every key that starts with `prefix`, laid out,
+    /// sized and limited like this one with a budget of its own.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "cow", "dog"]);
+    /// assert_eq!(trie.subtrie("c"), ParTrie::from_str_list(&["cat", "cow"]));
+    /// assert_eq!(trie.subtrie_stripped("c"), ParTrie::from_str_list(&["at", "ow"]));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the new trie runs out of its memory budget.
+    pub fn subtrie<K: TrieKey<T>>(&self, prefix: K) -> ParTrie<T> {
+        self.copy_below(prefix, false)
+    }
+
+    /// Like `subtrie` but with `prefix` taken off the front of every key.
+    pub fn subtrie_stripped<K: TrieKey<T>>(&self, prefix: K) -> ParTrie<T> {
+        self.copy_below(prefix, true)
+    }
+
+    fn copy_below<K: TrieKey<T>>(&self, prefix: K, strip: bool) -> ParTrie<T> {
+        let prefix = prefix.elements().collect::<Vec<_>>();
+        let skip = if strip { prefix.len() } else { 0 };
+        let pinned = self.pin();
+        let sub = ParTrie { raw: self.raw.empty_like() };
+        let g = epoch::pin();
+        for key in pinned.find(prefix) {
+            sub.raw
+                .insert_seq(key[skip..].iter().copied(), &g)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+        sub
+    }
+
+    /// Removes every key that starts with `prefix`, `prefix` included, and
+    /// returns how many there were. The keys are unlinked in one swap, a
+    /// reader sees either all of them or none of them. Waits for the
+    /// inserts already running to finish and holds off new ones until the
+    /// swap is done.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "car", "cow"]);
+    /// assert_eq!(trie.remove_prefix("ca"), 2);
+    /// assert!(!trie.contains("cat") && trie.contains("cow"));
+    /// assert_eq!(trie.next_elements("c"), vec![('o', 1)]);
+    /// ```
+    pub fn remove_prefix<K: TrieKey<T>>(&self, prefix: K) -> usize {
+        let g = epoch::pin();
+        self.raw
+            .remove_prefix(prefix.elements(), &g)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Removes every key `keep` returns false for. The keys are collected
+    /// and handed to `keep` first, then the rejected ones are removed in
+    /// one go that waits for running inserts like `remove_prefix`. A key
+    /// inserted while `keep` is being called is kept, unless it was
+    /// already in the trie and rejected.
+    ///
+    /// Unlike `remove_prefix` the keys go one at a time, a reader can see
+    /// some of them gone and others not yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "cow", "dog", "do"]);
+    /// trie.retain(|key| key.len() == 3);
+    /// assert!(trie.contains("cat") && trie.contains("dog") && !trie.contains("do"));
+    /// ```
+    pub fn retain<F: FnMut(&[T]) -> bool>(&self, mut keep: F) {
+        let gone = self.find(Vec::new()).into_iter().filter(|key| !keep(key));
+        let gone = gone.collect::<Vec<_>>();
+        self.raw
+            .remove_keys(gone, &epoch::pin())
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Empties the trie like `clear` and yields every key it held. The
+    /// keys are walked in the swapped out tree, so a key inserted once the
+    /// swap is done stays in the trie and is not yielded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "cow"]);
+    /// let mut keys = trie.drain().collect::<Vec<_>>();
+    /// keys.sort();
+    /// assert_eq!(keys, vec![vec!['c', 'a', 't'], vec!['c', 'o', 'w']]);
+    /// assert!(trie.is_empty());
+    /// ```
+    pub fn drain(&self) -> impl Iterator<Item = Vec<T>> {
+        let g = epoch::pin();
+        let mut found = Found::new();
+        self.raw.drain(&mut found, &g);
+        found.into_iter()
+    }
+
+    /// Moves every key that starts with `from` so that it starts with `to`
+    /// instead and returns how many keys moved. The move is a single swap,
+    /// a reader sees every key under `from` or every key under `to` but
+    /// never a key under both or under neither, and readers are never
+    /// held up. Waits for the inserts already running to finish and holds
+    /// off new ones until the swap is done.
+    ///
+    /// Only the nodes along the two paths are copied, the subtree below
+    /// `from` is moved as it is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::{Error, ParTrie};
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["/a/b/c", "/a/b/d", "/a/e", "/x/z"]);
+    /// assert_eq!(trie.rename_prefix("/a/b", "/x/y"), Ok(2));
+    /// assert!(trie.contains("/x/y/c") && trie.contains("/x/y/d") && !trie.contains("/a/b/c"));
+    /// assert!(trie.contains("/a/e") && trie.contains("/x/z"));
+    ///
+    /// assert_eq!(trie.rename_prefix("/a", "/x"), Err(Error::DestinationNotEmpty));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If `to` already has keys below it or starts with `from`, if the
+    /// moved keys don't fit the trie's limits or alphabet, or if the trie
+    /// can't make the copies. Nothing is moved then.
+    pub fn rename_prefix<K: TrieKey<T>, L: TrieKey<T>>(&self, from: K, to: L) -> Result<usize, Error> {
+        let (from, to) = (from.elements().collect::<Vec<_>>(), to.elements().collect::<Vec<_>>());
+        self.raw.rename_prefix(&from, &to, &epoch::pin())
+    }
+
+    /// Like `find` for each of `prefixes`, in the order they were given.
+    /// See `TrieRef::find_batch`.
+    pub fn find_batch<K, I>(&self, prefixes: I) -> Vec<Found<T>>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        let g = epoch::pin();
+        let prefixes = prefixes.into_iter().map(|p| p.elements().collect()).collect::<Vec<_>>();
+        self.raw.find_batch(&prefixes, &batch_order(&prefixes), &g)
+    }
+
+    /// Returns each element that can follow `prefix` paired with the number
+    /// of keys that continue through it, without walking any deeper.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "car", "cow"]);
+    /// assert_eq!(trie.next_elements("c"), vec![('a', 2), ('o', 1)]);
+    /// ```
+    pub fn next_elements<K: TrieKey<T>>(&self, prefix: K) -> Vec<(T, usize)> {
+        let pinned = self.pin();
+        let next = pinned.next_elements(prefix);
+        next.into_iter().map(|(val, count)| (val.clone(), count)).collect()
+    }
+}
+
+/// Without the `rayon` feature `par_fold` is still there so code written
+/// against it builds either way, it just runs on the calling thread.
+#[cfg(not(feature = "rayon"))]
+impl<T> ParTrie<T>
+where
+    T: Clone + Eq + Send + Sync,
+{
+    /// Folds every key that starts with `prefix` into an `A`. Without the
+    /// `rayon` feature the subtree is split up by the children of `prefix`,
+    /// each part is folded from `identity` on this thread and the parts are
+    /// combined with `reduce` in the order `find` walks them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::<char>::from_str_list(&["cat", "cow", "dog"]);
+    /// assert_eq!(trie.par_fold("c", || 0, |sum, key| sum + key.len(), |a, b| a + b), 6);
+    /// ```
+    pub fn par_fold<K, A, I, F, R>(&self, prefix: K, identity: I, fold: F, reduce: R) -> A
+    where
+        K: TrieKey<T>,
+        A: Send,
+        I: Fn() -> A + Sync + Send,
+        F: Fn(A, &[T]) -> A + Sync + Send,
+        R: Fn(A, A) -> A + Sync + Send,
+    {
+        let mut cursor = self.cursor();
+        for val in prefix.elements() {
+            if !cursor.push(&val) {
+                return identity();
+            }
+        }
+        let mut acc = identity();
+        if cursor.is_terminal() {
+            acc = fold(acc, &cursor.prefix());
+        }
+        for val in cursor.next_elements() {
+            cursor.push(&val);
+            let part = cursor.completions().into_iter().fold(identity(), |acc, key| fold(acc, &key));
+            acc = reduce(acc, part);
+            cursor.pop();
+        }
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_utils::thread;
+    use rayon::prelude::*;
+
+    const WORDS: &[&str; 20] = &[
+        "the", "them", "code", "coder", "coding",
+        "crap", "help", "heft", "apple", "hello",
+        "like", "love", "life", "huge", "copy",
+        "cookie", "zebra", "zappy", "king", "trie",
+    ];
+
+    fn get_text() -> Vec<String> {
+        use std::fs::File;
+        use std::io::Read;
+        const DATA: &[&str] = &["data/1984.txt", "data/sun-rise.txt"];
+        let mut contents = String::new();
+        File::open(DATA[1])
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+            .split(|c: char| c.is_whitespace())
+            .map(|s| s.to_string())
+            .collect()
+    }
+    
+    /// An empty trie of each layout and kind the removal tests run on.
+    fn each_layout() -> [fn() -> ParTrie<char>; 7] {
+        [
+            ParTrie::new,
+            ParTrie::compressed,
+            || ParTrie::burst(8),
+            || ParTrie::builder().hashed().build(),
+            || ParTrie::builder().adaptive(4).build(),
+            || ParTrie::builder().sorted().build(),
+            || ParTrie::builder().max_nodes(100_000).build(),
+        ]
+    }
+
+    fn make_trie(words: &[String]) -> ParTrie<char> {
+        let trie = ParTrie::new();
+        for w in words {
+            trie.insert(w.chars());
+        }
+        trie
+    }
+
+    #[test]
+    fn load_trie_3() {
+        let words = "cat cow car bob".split_whitespace().collect::<Vec<_>>();
+        let t = ParTrie::new();
+
+        for word in words {
+            println!("{:?}", word);
+            t.insert(word.chars());
+        }
+
+        println!("{:#?}", t);
+        assert_eq!(t.len(), 2);
+    }
+
+    #[test]
     fn trie_seq_find() {
         let trie = ParTrie::new();
         trie.insert("cat".chars());
@@ -470,77 +2266,1144 @@ mod tests {
     }
 
     #[test]
-    fn complex_insert_search() {
-        let words = "cod code coder coding codes"
-            .split_whitespace()
+    fn complex_insert_search() {
+        let words = "cod code coder coding codes"
+            .split_whitespace()
+            .collect::<Vec<_>>();
+        
+        let t = ParTrie::new();
+        for word in words {
+            t.insert(word.chars());
+        }
+        let search = t.find("c".chars());
+        println!("{:?}", search);
+        assert_eq!(search.collected.len(), 5);
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_WORDS_array() {
+        let t = ParTrie::<char>::from_str_list(WORDS);
+        for (i, word) in WORDS.iter().enumerate() {
+            let found = t.find(word.chars());
+            println!("{:?}", found.as_collected());
+            assert!(
+                found.as_collected().contains(&WORDS[i].chars().collect::<Vec<_>>().as_slice())
+            );
+        }
+    }
+
+    #[test]
+    fn move_thread_trie() {
+        let words = "cat cow car bob".split_whitespace().collect::<Vec<_>>();
+        let t = ParTrie::new();
+        thread::scope(|scope| {
+            scope.spawn(|_| {
+                for word in words {
+                    t.insert(word.chars());
+                }
+            });
+        })
+        .unwrap();
+        println!("{:#?}", t);
+        assert_eq!(t.len(), 2);
+    }
+    #[test]
+    fn rayon_insert() {
+        let t = ParTrie::new();
+        WORDS.par_iter().for_each(|word| {
+            t.insert(word.chars());
+        });
+        WORDS.par_iter().enumerate().for_each(|(i, word)| {
+            let found = t.find(word.chars());
+            println!("{:?}", found.as_collected());
+            assert!(
+                found.as_collected().contains(&WORDS[i].chars().collect::<Vec<_>>().as_slice())
+            );
+        });
+    }
+
+    #[test]
+    fn wide_concurrent_insert() {
+        let t = ParTrie::new();
+        (0..2000_u32).into_par_iter().for_each(|i| {
+            t.insert(vec![i % 1000, i]);
+        });
+        assert_eq!(t.len(), 1000);
+        (0..2000_u32).into_par_iter().for_each(|i| {
+            let found = t.find([i % 1000]);
+            assert!(found.as_collected().contains(&[i % 1000, i].as_ref()));
+        });
+    }
+
+    #[test]
+    fn all_words() {
+        let t = ParTrie::new();
+        let words = get_text();
+
+        words.par_iter().for_each(|word| {
+            t.insert(word.chars());
+        });
+        words.par_iter().enumerate().for_each(|(_i, word)| {
+            let found = t.find(word.chars());
+            println!("{:?}", found.as_collected());
+            assert!(
+                found.as_collected().contains(&word.chars().collect::<Vec<_>>().as_slice())
+            );
+        });
+    }
+
+    #[test]
+    fn cursor_push_pop() {
+        let words = WORDS.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let t = make_trie(&words);
+        let mut cursor = t.cursor();
+        for c in "cod".chars() {
+            assert!(cursor.push(&c));
+        }
+        assert!(!cursor.is_terminal());
+        assert_eq!(cursor.next_elements().collect::<Vec<_>>(), vec!['e', 'i']);
+        assert_eq!(cursor.completions().as_collected().len(), 3);
+
+        assert!(cursor.push(&'e'));
+        assert!(cursor.is_terminal());
+        assert!(!cursor.push(&'x'));
+        assert_eq!(cursor.depth(), 4);
+
+        assert!(cursor.pop() && cursor.pop());
+        assert_eq!(cursor.prefix(), vec!['c', 'o']);
+        assert_eq!(cursor.completions().as_collected().len(), 5);
+
+        while cursor.pop() {}
+        assert_eq!(cursor.completions().as_collected().len(), WORDS.len());
+    }
+
+    #[test]
+    fn cursor_sees_concurrent_insert() {
+        let t = ParTrie::new();
+        t.insert("ca".chars());
+        let mut cursor = t.cursor();
+        assert!(cursor.push(&'c'));
+        thread::scope(|scope| {
+            scope.spawn(|_| {
+                for word in &["cat", "cow", "car"] {
+                    t.insert(word.chars());
+                }
+            });
+        })
+        .unwrap();
+        assert_eq!(cursor.next_elements().collect::<Vec<_>>(), vec!['a', 'o']);
+        assert!(cursor.push(&'a') && cursor.push(&'r'));
+        assert!(cursor.is_terminal());
+    }
+
+    #[test]
+    fn cursor_follows_concurrent_edge_split() {
+        let t = ParTrie::compressed();
+        t.insert("abcdef");
+        let mut cursor = t.cursor();
+        assert!(cursor.push(&'a') && cursor.push(&'b'));
+        thread::scope(|scope| {
+            scope.spawn(|_| {
+                for word in &["abx", "aby", "abz"] {
+                    t.insert(*word);
+                }
+            });
+        })
+        .unwrap();
+        let mut next = cursor.next_elements().collect::<Vec<_>>();
+        next.sort_unstable();
+        assert_eq!(next, vec!['c', 'x', 'y', 'z']);
+        assert_eq!(cursor.completions().len(), 4);
+        assert!(cursor.push(&'y') && cursor.is_terminal());
+        assert!(cursor.pop() && cursor.push(&'c') && cursor.push(&'d'));
+        assert_eq!(cursor.prefix(), "abcd".chars().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cursor_follows_concurrent_burst() {
+        let t = ParTrie::burst(2);
+        t.insert("abcdef");
+        let mut cursor = t.cursor();
+        // the `b` is in the bucket of the root's only child
+        assert!(cursor.push(&'a') && cursor.push(&'b'));
+        thread::scope(|scope| {
+            scope.spawn(|_| {
+                for word in &["abx", "aby", "abz"] {
+                    t.insert(*word);
+                }
+            });
+        })
+        .unwrap();
+        let mut next = cursor.next_elements().collect::<Vec<_>>();
+        next.sort_unstable();
+        assert_eq!(next, vec!['c', 'x', 'y', 'z']);
+        assert_eq!(cursor.completions().len(), 4);
+        assert!(cursor.push(&'y') && cursor.is_terminal());
+        assert!(cursor.pop() && cursor.push(&'c') && cursor.push(&'d'));
+        assert_eq!(cursor.prefix(), "abcd".chars().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cursor_pins_per_call() {
+        let t = ParTrie::<char>::from_str_list(&["cat", "car", "cow"]);
+        let mut cursor = t.cursor();
+        assert!(cursor.push(&'c') && cursor.push(&'a'));
+        assert!(!epoch::is_pinned());
+        // the elements are walked again once their nodes are gone
+        assert_eq!(t.remove_prefix("ca"), 2);
+        assert!(cursor.next_elements().next().is_none() && !cursor.push(&'t'));
+        t.insert("cab");
+        assert!(cursor.push(&'b') && cursor.is_terminal());
+        assert_eq!(cursor.prefix(), vec!['c', 'a', 'b']);
+
+        let t = ParTrie::compressed();
+        t.insert("abcdef");
+        let pinned = t.pin();
+        let mut cursor = pinned.cursor();
+        assert!(cursor.push(&'a') && cursor.push(&'b'));
+        pinned.insert("abx");
+        assert_eq!(cursor.next_elements().collect::<Vec<_>>().len(), 2);
+        assert!(cursor.push(&'x') && cursor.is_terminal());
+        assert_eq!(cursor.completions().as_collected(), vec![&[&'a', &'b', &'x']]);
+    }
+
+    #[test]
+    fn next_element_counts() {
+        let t = ParTrie::<char>::from_str_list(WORDS);
+        t.insert("code".chars());
+        assert_eq!(t.next_elements("co".chars()), vec![('d', 3), ('p', 1), ('o', 1)]);
+        assert_eq!(t.next_elements("coding".chars()), vec![]);
+        assert_eq!(t.next_elements("x".chars()), vec![]);
+
+        let total = t.next_elements("").iter().map(|(_, n)| n).sum::<usize>();
+        assert_eq!(total, WORDS.len());
+    }
+
+    #[test]
+    fn rayon_next_element_counts() {
+        let t = ParTrie::new();
+        let words = get_text();
+        words.par_iter().for_each(|word| {
+            t.insert(word.chars());
+        });
+
+        let mut distinct = words.iter().filter(|w| !w.is_empty()).collect::<Vec<_>>();
+        distinct.sort();
+        distinct.dedup();
+        let total = t.next_elements("").iter().map(|(_, n)| n).sum::<usize>();
+        assert_eq!(total, distinct.len());
+    }
+
+    #[test]
+    fn trie_key_impls() {
+        let t = ParTrie::new();
+        assert!(t.insert("cat"));
+        assert!(t.insert(String::from("cow")));
+        let cat = String::from("cat");
+        assert!(!t.insert(&cat));
+        assert!(t.contains(cat));
+        assert!(t.contains("cow".chars()));
+        assert!(!t.contains("co"));
+        assert!(!t.contains("coward"));
+
+        let paths = ParTrie::new();
+        paths.insert(std::path::PathBuf::from("/usr/local/bin"));
+        paths.insert(std::path::Path::new("/usr/lib"));
+        assert!(paths.contains(std::path::Path::new("/usr/lib")));
+        assert_eq!(paths.find(std::path::Path::new("/usr")).as_collected().len(), 2);
+
+        let ips = ParTrie::new();
+        let v4: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let v6: std::net::IpAddr = "::1".parse().unwrap();
+        ips.insert(v4);
+        ips.insert(v6);
+        assert!(ips.contains([10_u8, 0, 0, 1]));
+        assert!(ips.contains(v6));
+        assert_eq!(ips.find(&[10_u8][..]).as_collected(), vec![&[10, 0, 0, 1][..]]);
+
+        // a v4 key is a prefix of the v6 keys that start with its octets
+        let mapped: std::net::IpAddr = "a00:1::1".parse().unwrap();
+        ips.insert(mapped);
+        assert!(ips.contains(v4) && ips.contains(mapped));
+        assert_eq!(ips.find(v4).as_collected().len(), 2);
+    }
+
+    #[test]
+    fn borrowed_slice_queries() {
+        let t: ParTrie<String> = ParTrie::new();
+        let words = "the cat sat on the mat"
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        assert!(t.insert_slice(&words));
+        assert!(t.insert_slice(&words[..2]));
+        assert!(!t.insert_slice(&words[..2]));
+
+        assert!(t.contains_slice(&["the", "cat"]));
+        assert!(!t.contains_slice(&["the", "cat", "sat"]));
+        assert_eq!(t.find_slice(&["the"]).len(), 2);
+        assert!(t.find_slice(&["a"]).is_empty());
+
+        let mut found = Found::new();
+        t.find_into(&words[..3], &mut found);
+        assert_eq!(found.as_collected(), vec![words.as_slice()]);
+        t.find_into(&words[..1], &mut found);
+        assert_eq!(found.len(), 2);
+        t.find_into(&["dog".to_string()], &mut found);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn string_api() {
+        let mut t: ParTrie<char> = WORDS.iter().copied().collect();
+        t.extend(vec!["cod", "trie"]);
+        assert!(t.insert_str("tried"));
+        assert!(t.contains_str("cod"));
+        assert!(!t.contains_str("tri"));
+
+        let mut words = t.complete("tri").collect::<Vec<_>>();
+        words.sort();
+        assert_eq!(words, vec!["trie", "tried"]);
+
+        let mut bytes: ParTrie<u8> = WORDS.iter().map(|w| w.as_bytes()).collect();
+        bytes.extend(vec![&b"\xff\x00"[..]]);
+        assert!(bytes.contains_bytes(b"\xff\x00"));
+        assert!(!bytes.insert_bytes(b"help"));
+        assert_eq!(bytes.complete_bytes(b"he").count(), 3);
+    }
+
+    #[test]
+    fn alphabet_trie() {
+        let lower: &dyn Alphabet<char> = &LowerAscii;
+        for idx in 0..lower.size() {
+            assert_eq!(lower.index(&lower.element(idx)), Some(idx));
+        }
+
+        let trie = ParTrie::with_alphabet(Dna);
+        let reads = (0..2048_u32)
+            .map(|i| (0..6).map(|j| b"ACGT"[(i >> (j * 2)) as usize & 3]).collect::<Vec<_>>())
             .collect::<Vec<_>>();
-        
-        let t = ParTrie::new();
-        for word in words {
-            t.insert(word.chars());
+        reads.par_iter().for_each(|read| {
+            assert!(trie.insert(read));
+        });
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.find(b"AC").len(), 128);
+        assert!(reads.iter().all(|read| trie.contains(read)));
+
+        assert_eq!(trie.try_insert(b"TTN"), Err(Error::NotInAlphabet { position: 2 }));
+        // the rejected key did not leave a node behind for the `N`
+        assert_eq!(trie.next_elements(b"TT").len(), 4);
+        assert_eq!(trie.find(b"TTN").len(), 0);
+        assert!(trie.contains_slice(&[&b'A'; 6]));
+
+        let mut cursor = trie.cursor();
+        assert!(cursor.push(&b'G') && !cursor.push(&b'N'));
+        assert_eq!(cursor.next_elements().collect::<Vec<_>>(), vec![b'A', b'C', b'G', b'T']);
+    }
+
+    #[test]
+    fn hashed_children() {
+        let trie = ParTrie::hashed();
+        let words = get_text();
+        let tokens = (0..20_000).map(|i| format!("tok{}", i)).collect::<Vec<_>>();
+        thread::scope(|s| {
+            s.spawn(|_| {
+                tokens.par_iter().for_each(|tok| {
+                    trie.insert(vec!["root".to_string(), tok.clone()]);
+                    assert!(trie.contains(vec!["root".to_string(), tok.clone()]));
+                });
+            });
+            // the same words from two sides so racing adds meet in one table
+            s.spawn(|_| words.par_iter().for_each(|w| { trie.insert(vec![w.clone()]); }));
+            words.par_iter().for_each(|w| { trie.insert(vec![w.clone()]); });
+        })
+        .unwrap();
+
+        let mut distinct = words.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(trie.len(), distinct.len() + 1);
+        assert_eq!(trie.next_elements(vec!["root".to_string()]).len(), tokens.len());
+        assert!(trie.contains_slice(&["root", "tok7"]));
+        assert!(!trie.contains(vec!["root".to_string(), "tok20000".to_string()]));
+        assert_eq!(trie.find(vec!["root".to_string()]).len(), tokens.len());
+
+        // borrowed lookups probe the table like owned ones
+        assert!(tokens.iter().all(|tok| trie.contains_slice(&["root", tok.as_str()])));
+        assert!(!trie.contains_slice(&["root", "tok20000"]));
+        assert_eq!(trie.find_slice(&["root"]).len(), tokens.len());
+        assert_eq!(trie.pin().find_slice(&["root", "tok1"]).len(), 1);
+        let adaptive = ParTrie::builder().adaptive(8).build();
+        for tok in &tokens[..100] {
+            adaptive.insert(vec!["root".to_string(), tok.clone()]);
         }
-        let search = t.find("c".chars());
-        println!("{:?}", search);
-        assert_eq!(search.collected.len(), 5);
+        assert!(tokens[..100].iter().all(|tok| adaptive.contains_slice(&["root", tok.as_str()])));
+        assert!(!adaptive.contains_slice(&["root"]) && adaptive.find_slice(&["root"]).len() == 100);
+    }
+
+    /// Fills `trie` and a plain trie with the same words from `data/1984.txt`
+    /// and checks every query answers the same on both.
+    fn assert_matches_plain(trie: &ParTrie<char>) -> ParTrie<char> {
+        use std::fs::File;
+        use std::io::Read;
+        let mut contents = String::new();
+        File::open("data/1984.txt").unwrap().read_to_string(&mut contents).unwrap();
+        let words = contents.split_whitespace().take(20_000).collect::<Vec<_>>();
+
+        let plain = ParTrie::<char>::new();
+        words.par_iter().for_each(|w| {
+            plain.insert(*w);
+            trie.insert(*w);
+        });
+        trie.insert("");
+        plain.insert("");
+
+        let sorted = |found: Found<char>| {
+            let mut keys = found.into_iter().collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+        // the order of next elements depends on the layout
+        let counts = |counts: Vec<(char, usize)>| {
+            let mut counts = counts;
+            counts.sort();
+            counts
+        };
+        assert_eq!(trie.len(), plain.len());
+        assert_eq!(sorted(trie.find("")), sorted(plain.find("")));
+        for w in words.iter().step_by(50) {
+            let chars = w.chars().collect::<Vec<_>>();
+            for end in 1..=chars.len() {
+                let prefix = &chars[..end];
+                assert_eq!(trie.contains(prefix), plain.contains(prefix));
+                assert_eq!(counts(trie.next_elements(prefix)), counts(plain.next_elements(prefix)));
+                assert_eq!(sorted(trie.find(prefix)), sorted(plain.find(prefix)));
+            }
+            let refs = chars.iter().collect::<Vec<_>>();
+            assert!(trie.contains_slice(&refs));
+            assert_eq!(trie.find_slice(&refs[..1]).len(), plain.find_slice(&refs[..1]).len());
+
+            let (mut a, mut b) = (trie.cursor(), plain.cursor());
+            for c in chars.iter().chain(&['#']) {
+                let (mut x, mut y) = (a.next_elements().collect::<Vec<_>>(), b.next_elements().collect::<Vec<_>>());
+                x.sort();
+                y.sort();
+                assert_eq!(x, y);
+                assert_eq!(a.push(c), b.push(c));
+                assert_eq!(a.is_terminal(), b.is_terminal());
+                assert_eq!(sorted(a.completions()), sorted(b.completions()));
+            }
+            assert!(a.pop() && b.pop() && a.prefix() == b.prefix());
+        }
+        plain
     }
 
     #[test]
-    #[allow(non_snake_case)]
-    fn test_WORDS_array() {
-        let t = ParTrie::<char>::from_str_list(WORDS);
-        for (i, word) in WORDS.iter().enumerate() {
-            let found = t.find(word.chars());
-            println!("{:?}", found.as_collected());
-            assert!(
-                found.as_collected().contains(&WORDS[i].chars().collect::<Vec<_>>().as_slice())
-            );
+    fn compressed_matches_plain() {
+        let compressed = ParTrie::compressed();
+        let plain = assert_matches_plain(&compressed);
+        assert!(compressed.raw.node_count() * 2 < plain.raw.node_count());
+    }
+
+    #[test]
+    fn burst_matches_plain() {
+        for threshold in &[1, 4, 32] {
+            let burst = ParTrie::burst(*threshold);
+            let plain = assert_matches_plain(&burst);
+            assert!(burst.raw.node_count() < plain.raw.node_count());
         }
     }
 
     #[test]
-    fn move_thread_trie() {
-        let words = "cat cow car bob".split_whitespace().collect::<Vec<_>>();
-        let t = ParTrie::new();
-        thread::scope(|scope| {
-            scope.spawn(|_| {
-                for word in words {
-                    t.insert(word.chars());
-                }
+    fn builder_layouts() {
+        let tries = vec![
+            ParTrie::with_capacity(64),
+            ParTrie::builder().node_capacity(1).growth_factor(1.5).build(),
+            ParTrie::builder().resize_policy(ResizePolicy::Single).node_capacity(2).build(),
+            ParTrie::builder().sorted().build(),
+            ParTrie::builder().root_capacity(100).hashed().build(),
+            ParTrie::builder().adaptive(8).resize_policy(ResizePolicy::Single).build(),
+            ParTrie::builder().root_capacity(3).adaptive(2).burst(4).build(),
+        ];
+        for trie in &tries {
+            assert_matches_plain(trie);
+        }
+    }
+
+    #[test]
+    fn reserve_and_compact() {
+        let words = get_text();
+        for trie in &[ParTrie::new(), ParTrie::builder().hashed().build(), ParTrie::builder().adaptive(4).build()] {
+            trie.reserve(5000);
+            assert!(trie.compact() > 0);
+            assert_eq!(trie.shrink_to_fit(), 0);
+            trie.reserve(5000);
+            thread::scope(|s| {
+                s.spawn(|_| {
+                    while trie.len() < 10 {
+                        trie.compact();
+                    }
+                    trie.shrink_to_fit();
+                });
+                words.par_iter().for_each(|w| {
+                    trie.insert(w);
+                    assert!(trie.contains(w));
+                });
+            })
+            .unwrap();
+            trie.shrink_to_fit();
+            assert_eq!(trie.shrink_to_fit(), 0);
+            assert_eq!(trie.compact(), 0);
+            assert!(words.iter().all(|w| trie.contains(w)));
+        }
+    }
+
+    #[test]
+    fn heap_accounting() {
+        let words = get_text();
+        let tries: Vec<ParTrie<char>> = vec![
+            ParTrie::new(),
+            ParTrie::with_capacity(100),
+            ParTrie::builder().sorted().build(),
+            ParTrie::builder().hashed().resize_policy(ResizePolicy::Single).build(),
+            ParTrie::builder().adaptive(4).build(),
+            ParTrie::compressed(),
+            ParTrie::burst(4),
+        ];
+        for trie in &tries {
+            words.par_iter().for_each(|w| { trie.insert(w); });
+            assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+            let before = trie.heap_size();
+            let freed = trie.shrink_to_fit();
+            assert_eq!(trie.heap_size(), before - freed);
+            assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+        }
+    }
+
+    #[test]
+    fn memory_budget() {
+        let words = get_text();
+        for budget in &[0, 1 << 10, 1 << 13] {
+            let tries: Vec<ParTrie<char>> = vec![
+                ParTrie::builder().memory_budget(*budget).build(),
+                ParTrie::builder().memory_budget(*budget).sorted().build(),
+                ParTrie::builder().memory_budget(*budget).compressed().build(),
+                ParTrie::builder().memory_budget(*budget).burst(4).build(),
+            ];
+            for trie in &tries {
+                let added = words
+                    .par_iter()
+                    .filter_map(|w| match trie.try_insert(w) {
+                        Ok(_) => Some(w),
+                        Err(e) => {
+                            assert_eq!(e, Error::OverBudget { budget: *budget });
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                assert!(added.len() < words.len());
+                assert!(added.iter().all(|w| trie.contains(*w)));
+                assert!(trie.heap_size() <= *budget);
+                assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+            }
+        }
+
+        // an edge longer than the budget is refused before it is allocated
+        let trie = ParTrie::builder().memory_budget(1 << 10).compressed().build();
+        let long = "a".repeat(1 << 20);
+        assert_eq!(trie.try_insert(long.as_str()), Err(Error::OverBudget { budget: 1 << 10 }));
+        assert_eq!(trie.heap_size(), 0);
+        assert!(trie.try_insert("abc").is_ok());
+    }
+
+    #[test]
+    fn hardening_limits() {
+        let trie = ParTrie::builder().max_key_len(8).max_fanout(26).max_nodes(200).build();
+        let long = "a".repeat(1 << 20);
+        assert_eq!(trie.try_insert(long.as_str()), Err(Error::KeyTooLong { max: 8 }));
+        assert!(trie.is_empty());
+
+        let words = get_text();
+        let errors = words
+            .par_iter()
+            .filter_map(|w| trie.try_insert(w).err())
+            .collect::<Vec<_>>();
+        assert!(errors.contains(&Error::TooManyNodes { max: 200 }));
+        assert!(trie.raw.node_count() <= 200);
+        assert_eq!(trie.raw.nodes.load(SeqCst), trie.raw.node_count());
+        assert!(words.iter().filter(|w| w.chars().count() <= 8).any(|w| trie.contains(w)));
+
+        let trie = ParTrie::builder().max_fanout(3).build();
+        for c in 'a'..='c' {
+            assert!(trie.try_insert(c.to_string()).is_ok());
+        }
+        assert_eq!(trie.try_insert("d"), Err(Error::TooManyChildren { max: 3 }));
+        assert_eq!(trie.try_insert("ad"), Ok(true));
+        assert_eq!(trie.try_insert("a"), Ok(false));
+    }
+
+    #[test]
+    fn deep_keys_are_iterative() {
+        // a thread's stack is far too small to recurse this deep
+        let long = "ab".repeat(100_000);
+        for trie in &[ParTrie::new(), ParTrie::compressed(), ParTrie::burst(2)] {
+            trie.insert(long.as_str());
+            trie.insert(&long[..150_000]);
+            assert!(trie.contains(long.as_str()));
+            assert_eq!(trie.find("ab").len(), 2);
+            assert_eq!(trie.next_elements(&long[..1000]), vec![('a', 2)]);
+            assert_eq!(trie.cursor().completions().len(), 2);
+            trie.raw.node_count();
+        }
+        let bytes = ByteTrie::new();
+        bytes.insert(long.as_bytes());
+        assert_eq!(bytes.find(b"a").len(), 1);
+    }
+
+    #[test]
+    fn pinned_borrows() {
+        let words = get_text();
+        for trie in [ParTrie::new(), ParTrie::compressed(), ParTrie::burst(4)] {
+            let pinned = trie.pin();
+            for w in &words {
+                pinned.insert(w);
+            }
+            assert!(words.iter().all(|w| pinned.contains(w)));
+            for prefix in ["", "t", "th", "the", "zzz"] {
+                let owned = trie.find(prefix).into_iter().collect::<Vec<_>>();
+                let borrowed = pinned.find(prefix).into_iter().map(|k| k.into_iter().copied().collect::<Vec<_>>());
+                assert_eq!(borrowed.collect::<Vec<_>>(), owned);
+                let next = pinned.next_elements(prefix).into_iter().map(|(c, n)| (*c, n)).collect::<Vec<_>>();
+                assert_eq!(next, trie.next_elements(prefix));
+            }
+        }
+    }
+
+    #[test]
+    fn batches_match_single_calls() {
+        let words = get_text();
+        let queries = words.iter().rev().map(|w| format!("{}s", w)).chain(words.iter().cloned()).collect::<Vec<_>>();
+        for (batch, single) in [
+            (ParTrie::new(), ParTrie::new()),
+            (ParTrie::compressed(), ParTrie::compressed()),
+            (ParTrie::burst(4), ParTrie::burst(4)),
+            (ParTrie::builder().max_key_len(6).build(), ParTrie::builder().max_key_len(6).build()),
+        ] {
+            let added = batch.try_insert_batch(&words);
+            assert_eq!(added, words.iter().map(|w| single.try_insert(w)).collect::<Vec<_>>());
+            assert_eq!(batch.contains_batch(&queries), queries.iter().map(|q| single.contains(q)).collect::<Vec<_>>());
+
+            let found = batch.find_batch(&queries);
+            for (found, query) in found.into_iter().zip(&queries) {
+                // the batch went in sorted so children are in another order
+                let mut found = found.into_iter().collect::<Vec<_>>();
+                let mut expected = single.find(query).into_iter().collect::<Vec<_>>();
+                found.sort_unstable();
+                expected.sort_unstable();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_collect_and_iter() {
+        let words = get_text();
+        for trie in [words.par_iter().collect::<ParTrie<char>>(), {
+            let mut trie = ParTrie::burst(4);
+            trie.par_extend(words.par_iter());
+            trie
+        }] {
+            for prefix in ["", "t", "th", "zzz"] {
+                let keys = trie.par_iter_prefix(prefix).collect::<Vec<_>>();
+                assert_eq!(keys, trie.find(prefix).into_iter().collect::<Vec<_>>());
+            }
+            assert_eq!(trie.par_iter().count(), trie.find("").len());
+        }
+    }
+
+    #[test]
+    fn par_fold_with_or_without_rayon() {
+        use std::collections::BTreeSet;
+
+        let trie = ParTrie::<char>::from_str_list(WORDS);
+        let keys = trie.par_fold("co", || 0, |count, _| count + 1, |a, b| a + b);
+        assert_eq!(keys, trie.find("co").len());
+        let mut found = trie.par_fold(
+            "",
+            Vec::new,
+            |mut all, key| {
+                all.push(key.to_vec());
+                all
+            },
+            |mut a, b| {
+                a.extend(b);
+                a
+            },
+        );
+        found.sort();
+        assert_eq!(found, BTreeSet::from(&trie).into_iter().collect::<Vec<_>>());
+        assert_eq!(trie.par_fold("zz", || 0, |count, _| count + 1, |a, b| a + b), 0);
+
+        // every part starts from `identity` and is handed to `reduce`
+        let parts = trie.par_fold("co", || 1, |parts, _| parts, |a, b| a + b);
+        #[cfg(not(feature = "rayon"))]
+        assert_eq!(parts, 1 + trie.next_elements("co").len());
+        assert!(parts > 1);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_iter_long_chain() {
+        let key = (0..2000_usize).map(|i| char::from(b'a' + (i % 26) as u8)).collect::<String>();
+        for make in each_layout() {
+            let trie = make();
+            for len in (0..=key.len()).step_by(7) {
+                trie.insert(&key[..len]);
+            }
+            trie.insert(format!("{}z", &key[..1000]));
+            let keys = trie.par_iter().collect::<Vec<_>>();
+            assert_eq!(keys, trie.find("").into_iter().collect::<Vec<_>>());
+            assert_eq!(trie.par_iter_prefix(&key[..1000]).count(), 144);
+            assert_eq!(trie.par_iter_prefix("zz").count(), 0);
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_fold_while_inserting() {
+        let words = get_text();
+        let trie = words[..100].par_iter().collect::<ParTrie<char>>();
+        let chars = |trie: &ParTrie<char>| trie.par_fold("", || 0, |sum, key| sum + key.len(), |a, b| a + b);
+        let before = trie.find("").into_iter().map(|key| key.len()).sum::<usize>();
+        assert_eq!(chars(&trie), before);
+
+        thread::scope(|s| {
+            s.spawn(|_| {
+                words[100..].par_iter().for_each(|w| {
+                    trie.insert(w);
+                })
             });
+            for _ in 0..20 {
+                let during = chars(&trie);
+                assert!(during >= before);
+            }
         })
         .unwrap();
-        println!("{:#?}", t);
-        assert_eq!(t.len(), 2);
+        let after = trie.find("").into_iter().map(|key| key.len()).sum::<usize>();
+        assert_eq!(chars(&trie), after);
+
+        let histogram = trie.par_fold(
+            "t",
+            std::collections::HashMap::new,
+            |mut lens, key| {
+                *lens.entry(key.len()).or_insert(0) += 1;
+                lens
+            },
+            |mut a, b| {
+                b.into_iter().for_each(|(len, n)| *a.entry(len).or_insert(0) += n);
+                a
+            },
+        );
+        assert_eq!(histogram.values().sum::<usize>(), trie.find("t").len());
     }
+
     #[test]
-    fn rayon_insert() {
-        let t = ParTrie::new();
-        WORDS.par_iter().for_each(|word| {
-            t.insert(word.chars());
-        });
-        WORDS.par_iter().enumerate().for_each(|(i, word)| {
-            let found = t.find(word.chars());
-            println!("{:?}", found.as_collected());
-            assert!(
-                found.as_collected().contains(&WORDS[i].chars().collect::<Vec<_>>().as_slice())
-            );
-        });
+    fn set_traits() {
+        use std::collections::{BTreeSet, HashSet};
+
+        let words = get_text();
+        let plain = words.iter().collect::<ParTrie<char>>();
+        let mut sorted = ParTrie::builder().sorted().build();
+        sorted.extend(words.iter().rev());
+        let mut burst = ParTrie::burst(4);
+        burst.extend(words.iter().rev());
+        assert!(plain == sorted && sorted == burst);
+
+        for trie in [&plain, &sorted, &burst] {
+            let copy = trie.clone();
+            assert_eq!(&copy, trie);
+            assert_eq!(copy.next_elements("t"), trie.next_elements("t"));
+            copy.insert("zzz");
+            assert_ne!(&copy, trie);
+            assert!(!trie.contains("zzz"));
+        }
+        let budgeted = ParTrie::builder().memory_budget(1 << 16).build();
+        for w in &words {
+            budgeted.insert(w);
+        }
+        assert_eq!(budgeted.clone().heap_size(), budgeted.heap_size());
+
+        let state = RandomState::new();
+        assert_eq!(state.hash_one(&plain), state.hash_one(&sorted));
+        assert_eq!(state.hash_one(&plain), state.hash_one(&burst));
+        assert_ne!(state.hash_one(&plain), state.hash_one(ParTrie::<char>::new()));
+
+        let keys = BTreeSet::from(&plain);
+        assert_eq!(keys.len(), plain.find("").len());
+        assert_eq!(ParTrie::from(keys), plain);
+        let keys = HashSet::<Vec<char>>::from(&plain);
+        assert_eq!(ParTrie::from(keys), plain);
     }
 
     #[test]
-    fn all_words() {
-        let t = ParTrie::new();
+    fn set_operations() {
+        use std::collections::BTreeSet;
+
         let words = get_text();
+        let (left, right) = words.split_at(words.len() / 2);
+        let right = right.iter().chain(&left[..20]).collect::<Vec<_>>();
+        let makes: [fn() -> ParTrie<char>; 3] = [ParTrie::new, ParTrie::compressed, || ParTrie::burst(4)];
+        for make_a in makes {
+            for make_b in makes {
+                let mut a = make_a();
+                a.extend(left);
+                let mut b = make_b();
+                b.extend(right.iter().copied());
+                let (set_a, set_b) = (BTreeSet::from(&a), BTreeSet::from(&b));
 
-        words.par_iter().for_each(|word| {
-            t.insert(word.chars());
-        });
-        words.par_iter().enumerate().for_each(|(i, word)| {
-            let found = t.find(word.chars());
-            println!("{:?}", found.as_collected());
-            assert!(
-                found.as_collected().contains(&word.chars().collect::<Vec<_>>().as_slice())
-            );
-        });
+                assert_eq!(BTreeSet::from(&a.union(&b)), &set_a | &set_b);
+                assert_eq!(BTreeSet::from(&a.intersection(&b)), &set_a & &set_b);
+                assert_eq!(BTreeSet::from(&a.difference(&b)), &set_a - &set_b);
+                assert_eq!(BTreeSet::from(&a.symmetric_difference(&b)), &set_a ^ &set_b);
+                a.union_with(&b);
+                assert_eq!(BTreeSet::from(&a), &set_a | &set_b);
+                let mut a = make_a();
+                a.extend(left);
+                a.intersection_with(&b);
+                assert_eq!(BTreeSet::from(&a), &set_a & &set_b);
+                let mut a = make_a();
+                a.extend(left);
+                a.difference_with(&b);
+                assert_eq!(BTreeSet::from(&a), &set_a - &set_b);
+                assert_eq!(a.heap_size(), a.raw.measured_heap_size());
+            }
+        }
+    }
+
+    #[test]
+    fn set_operations_in_place() {
+        use std::collections::BTreeSet;
+
+        let words = get_text();
+        let (left, right) = words.split_at(words.len() / 2);
+        let mut right = right.iter().chain(&left[..20]).cloned().collect::<Vec<_>>();
+        right.extend(["th", "s", ""].iter().map(|w| w.to_string()));
+        let makes: [fn() -> ParTrie<char>; 3] = [ParTrie::new, ParTrie::compressed, || ParTrie::burst(4)];
+        for make_a in each_layout() {
+            for make_b in makes {
+                let mut b = make_b();
+                b.extend(&right);
+                let set_b = BTreeSet::from(&b);
+                // the keys `b` has are the ones that stay after an intersection
+                for intersect in [true, false] {
+                    let mut a = make_a();
+                    a.extend(left);
+                    a.insert("");
+                    let model = BTreeSet::from(&a)
+                        .into_iter()
+                        .filter(|key| set_b.contains(key) == intersect)
+                        .collect::<BTreeSet<_>>();
+                    if intersect {
+                        a.intersection_with(&b);
+                    } else {
+                        a.difference_with(&b);
+                    }
+                    assert_eq!(BTreeSet::from(&a), model);
+                    for (val, count) in a.next_elements("") {
+                        assert_eq!(count, model.iter().filter(|k| k.first() == Some(&val)).count());
+                    }
+                    assert_eq!(a.heap_size(), a.raw.measured_heap_size());
+                    if a.raw.limits.nodes.is_some() {
+                        assert_eq!(a.raw.nodes.load(SeqCst), a.raw.node_count());
+                    }
+                    assert_eq!(BTreeSet::from(&b), set_b);
+                }
+            }
+
+            // nothing is left of the branches once their keys are gone
+            let trie = make_a();
+            trie.insert("cat");
+            trie.insert("car");
+            trie.intersection_with(&ParTrie::new());
+            assert!(trie.is_empty() && trie.next_elements("").is_empty());
+            trie.insert("cow");
+            trie.difference_with(&trie.clone());
+            assert!(trie.is_empty() && trie.next_elements("").is_empty());
+        }
+    }
+
+    #[test]
+    fn remove_prefix_and_subtrie() {
+        use std::collections::BTreeSet;
+
+        let words = get_text();
+        for make in each_layout() {
+            let mut trie = make();
+            trie.extend(&words);
+            let mut model = BTreeSet::from(&trie);
+            for prefix in ["th", "a", "zzz", "w", "s", "su", ""] {
+                let prefix = prefix.chars().collect::<Vec<_>>();
+                let below = model.iter().filter(|k| k.starts_with(&prefix)).cloned().collect::<BTreeSet<_>>();
+                assert_eq!(BTreeSet::from(&trie.subtrie(&prefix)), below);
+                let stripped = below.iter().map(|k| k[prefix.len()..].to_vec()).collect();
+                assert_eq!(BTreeSet::from(&trie.subtrie_stripped(&prefix)), stripped);
+
+                assert_eq!(trie.remove_prefix(&prefix), below.len());
+                model.retain(|k| !k.starts_with(&prefix));
+                assert_eq!(BTreeSet::from(&trie), model);
+                for (val, count) in trie.next_elements("") {
+                    assert_eq!(count, model.iter().filter(|k| k.first() == Some(&val)).count());
+                }
+                assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+                if trie.raw.limits.nodes.is_some() {
+                    assert_eq!(trie.raw.nodes.load(SeqCst), trie.raw.node_count());
+                }
+            }
+            assert!(trie.find("").is_empty() && trie.next_elements("").is_empty());
+            assert!(trie.is_empty());
+            trie.insert("sun");
+            assert_eq!(trie.find("").len(), 1);
+        }
+
+        // nothing is left of a branch once its last prefix is removed
+        let trie = ParTrie::from_str_list(&["cat", "car"]);
+        assert_eq!(trie.remove_prefix("c"), 2);
+        assert!(trie.is_empty());
+        assert!(!trie.cursor().push(&'c'));
+        let trie = ParTrie::from_str_list(&["cat", "car", "dog"]);
+        assert_eq!(trie.remove_prefix("ca"), 2);
+        assert_eq!(trie.len(), 1);
+        assert!(!trie.cursor().push(&'c'));
+
+        // removing never fails on the limits that adding keys is held to
+        for (mut trie, max) in [(ParTrie::builder().max_nodes(7).build(), 7), (ParTrie::builder().burst(4).max_nodes(2).build(), 2)] {
+            trie.extend(vec!["cat", "car", "dog"]);
+            assert_eq!(trie.try_insert("eel"), Err(Error::TooManyNodes { max }));
+            assert_eq!(trie.remove_prefix("cat"), 1);
+            assert_eq!(trie.remove_prefix("d"), 1);
+            assert_eq!(BTreeSet::from(&trie), BTreeSet::from(&ParTrie::from_str_list(&["car"])));
+        }
+        let mut trie = ParTrie::burst(4);
+        trie.extend(vec!["cat", "car", "cab"]);
+        let mut full = ParTrie::builder().burst(4).memory_budget(trie.heap_size()).build();
+        full.extend(vec!["cat", "car", "cab"]);
+        assert!(full.try_insert("dog").is_err());
+        assert_eq!(full.remove_prefix("car"), 1);
+        assert_eq!(full.find("").len(), 2);
+
+        // readers see the whole subtree or none of it
+        let trie = ParTrie::new();
+        for w in &words {
+            trie.insert(w);
+        }
+        let all = trie.find("t").len();
+        let removed = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|_| {
+                    while !removed.load(SeqCst) {
+                        let seen = trie.find("t").len();
+                        assert!(seen == all || seen == 0, "saw {} of {}", seen, all);
+                    }
+                });
+            }
+            assert_eq!(trie.remove_prefix("t"), all);
+            removed.store(true, SeqCst);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn clear_retain_and_drain() {
+        use std::collections::BTreeSet;
+
+        let words = get_text();
+        for make in each_layout() {
+            let mut trie = make();
+            trie.extend(&words);
+            trie.insert("");
+            let mut model = BTreeSet::from(&trie);
+            for len in [7, 3, 5] {
+                trie.retain(|key| key.len() < len || key.len() % len == 0);
+                model.retain(|key| key.len() < len || key.len() % len == 0);
+                assert_eq!(BTreeSet::from(&trie), model);
+                for (val, count) in trie.next_elements("") {
+                    assert_eq!(count, model.iter().filter(|k| k.first() == Some(&val)).count());
+                }
+                assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+                if trie.raw.limits.nodes.is_some() {
+                    assert_eq!(trie.raw.nodes.load(SeqCst), trie.raw.node_count());
+                }
+            }
+
+            trie.retain(|_| false);
+            assert!(trie.is_empty() && trie.find("").is_empty());
+            assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+            trie.extend(model.iter());
+            assert_eq!(trie.drain().collect::<BTreeSet<_>>(), model);
+            assert!(trie.is_empty() && !trie.contains(""));
+            assert_eq!(trie.heap_size(), 0);
+            trie.extend(&words);
+            trie.clear();
+            assert!(trie.find("").is_empty());
+            assert_eq!(trie.heap_size(), 0);
+            trie.insert("sun");
+            assert_eq!(trie.find("").len(), 1);
+        }
+
+        // nothing is left of the keys `retain` takes out
+        let trie = ParTrie::from_str_list(&["cat", "car"]);
+        trie.retain(|_| false);
+        assert!(trie.is_empty());
+        assert!(!trie.cursor().push(&'c'));
+        let trie = ParTrie::from_str_list(&["cat", "car", "dog"]);
+        trie.retain(|key| key[0] == 'd');
+        assert_eq!(trie.len(), 1);
+        assert!(!trie.cursor().push(&'c'));
+
+        // every key ends up drained or left in the trie, never both or
+        // neither, while other threads insert
+        let trie = ParTrie::new();
+        let drained = std::sync::Mutex::new(vec![]);
+        thread::scope(|s| {
+            let trie = &trie;
+            for chunk in words.chunks(words.len() / 4 + 1) {
+                s.spawn(move |_| {
+                    for w in chunk {
+                        trie.insert(w);
+                    }
+                });
+            }
+            for _ in 0..8 {
+                drained.lock().unwrap().extend(trie.drain());
+                trie.retain(|key| key.len() != 2);
+            }
+        })
+        .unwrap();
+        let mut drained = drained.into_inner().unwrap();
+        drained.extend(trie.drain());
+        let drained = drained.into_iter().filter(|k| k.len() != 2).collect::<BTreeSet<_>>();
+        let all = words.iter().map(|w| w.chars().collect::<Vec<_>>()).filter(|k| k.len() != 2);
+        assert!(all.collect::<BTreeSet<_>>().is_subset(&drained));
+    }
+
+    #[test]
+    fn rename_prefix_moves_subtrees() {
+        use std::collections::BTreeSet;
+
+        let words = get_text();
+        let renames = [
+            ("th", "zq"),
+            ("zqe", "the"),
+            ("ca", "cuq"),
+            ("in", "oxq"),
+            ("wh", "w"),
+            ("a", "aa"),
+            ("s", "z"),
+            ("he", "hex"),
+            ("", ""),
+        ];
+        for make in each_layout() {
+            let mut trie = make();
+            trie.extend(&words);
+            let mut model = BTreeSet::from(&trie);
+            for (from, to) in renames {
+                let (from, to) = (from.chars().collect::<Vec<_>>(), to.chars().collect::<Vec<_>>());
+                let below = |prefix: &[char], model: &BTreeSet<Vec<char>>| model.iter().filter(|k| k.starts_with(prefix)).count();
+                let expected = if below(&to, &model) > 0 {
+                    Err(Error::DestinationNotEmpty)
+                } else if below(&from, &model) == 0 {
+                    Ok(0)
+                } else if to.starts_with(&from) {
+                    Err(Error::MoveIntoItself)
+                } else {
+                    let moved = model.iter().filter(|k| k.starts_with(&from)).cloned().collect::<Vec<_>>();
+                    for key in &moved {
+                        model.remove(key);
+                        model.insert(to.iter().chain(&key[from.len()..]).copied().collect());
+                    }
+                    Ok(moved.len())
+                };
+                assert_eq!(trie.rename_prefix(&from, &to), expected);
+                assert_eq!(BTreeSet::from(&trie), model);
+                for (val, count) in trie.next_elements("") {
+                    assert_eq!(count, model.iter().filter(|k| k.first() == Some(&val)).count());
+                }
+                assert_eq!(trie.heap_size(), trie.raw.measured_heap_size());
+                if trie.raw.limits.nodes.is_some() {
+                    assert_eq!(trie.raw.nodes.load(SeqCst), trie.raw.node_count());
+                }
+            }
+            // into a prefix left empty by `remove_prefix`
+            trie.remove_prefix("b");
+            assert!(trie.rename_prefix("c", "b").unwrap() > 0);
+            assert!(trie.find("c").is_empty() && !trie.find("b").is_empty());
+        }
+
+        let mut trie = ParTrie::with_alphabet(Dna);
+        trie.extend(vec!["GATT", "GACA", "GT", "CAT"]);
+        assert_eq!(trie.rename_prefix("GA", "TT"), Ok(2));
+        assert_eq!(BTreeSet::from(&trie), BTreeSet::from(&ParTrie::from_str_list(&["TTTT", "TTCA", "GT", "CAT"])));
+        assert!(matches!(trie.rename_prefix("G", "X"), Err(Error::NotInAlphabet { .. })));
+
+        let trie = ParTrie::<char>::builder().max_key_len(4).build();
+        trie.insert("abcd");
+        assert_eq!(trie.rename_prefix("a", "xy"), Err(Error::KeyTooLong { max: 4 }));
+        assert!(trie.contains("abcd"));
+
+        // readers see every key under one prefix or the other
+        let trie = ParTrie::new();
+        for w in &words {
+            trie.insert(w);
+        }
+        let all = trie.find("t").len();
+        let moved = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|_| {
+                    while !moved.load(SeqCst) {
+                        let keys = trie.find("");
+                        let seen = keys.as_collected().iter().filter(|k| k.first() == Some(&'t') || k.first() == Some(&'Q')).count();
+                        assert_eq!(seen, all);
+                    }
+                });
+            }
+            s.spawn(|_| {
+                for w in words.iter().filter(|w| !w.starts_with(['t', 'q', 'Q'])) {
+                    trie.insert(w.to_uppercase());
+                }
+            });
+            assert_eq!(trie.rename_prefix("t", "Q"), Ok(all));
+            moved.store(true, SeqCst);
+        })
+        .unwrap();
+        assert!(trie.find("t").is_empty());
+    }
+
+    #[test]
+    fn elements_need_only_eq() {
+        // neither Clone nor Debug
+        #[derive(PartialEq, Eq, Hash)]
+        struct Token(u32);
+
+        let tokens = |ids: &[u32]| ids.iter().map(|&id| Token(id)).collect::<Vec<_>>();
+        for trie in [ParTrie::new(), ParTrie::builder().hashed().build()] {
+            assert!(trie.insert(tokens(&[1, 2, 3])));
+            assert!(trie.insert(tokens(&[1, 4])));
+            assert!(!trie.insert(tokens(&[1, 2, 3])));
+            assert!(trie.contains(tokens(&[1, 4])) && !trie.contains(tokens(&[1, 2])));
+            assert!(trie.contains_slice(&[&Token(1), &Token(2), &Token(3)]));
+
+            let pinned = trie.pin();
+            let mut cursor = pinned.cursor();
+            assert!(cursor.push(&Token(1)));
+            let mut next = cursor.next_elements().map(|t| t.0).collect::<Vec<_>>();
+            next.sort_unstable();
+            assert_eq!(next, vec![2, 4]);
+            assert!(cursor.push(&Token(4)) && cursor.is_terminal());
+            assert_eq!(cursor.path().map(|t| t.0).collect::<Vec<_>>(), vec![1, 4]);
+        }
     }
 }
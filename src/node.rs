@@ -1,141 +1,1417 @@
-use std::cell::UnsafeCell;
+use std::alloc;
+use std::borrow::Borrow;
+use std::cmp::{self, Ordering};
 use std::fmt;
-use std::marker::PhantomData;
-use std::mem::{self, ManuallyDrop, MaybeUninit};
-use std::ops::{Deref, DerefMut};
+use std::mem;
 use std::ptr;
-use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering::*};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+use std::thread;
 
-// use crossbeam::epoch::{self, Atomic, Guard, Owned, Shared};
 use crossbeam_epoch::{self as epoch, Guard};
 
-use crate::buffer::ParVec;
+use crate::builder::ResizePolicy;
+use crate::key;
+use crate::Error;
+use crate::pointers::{self, Atomic, Owned, Shared};
 
-use crate::pointers::{Atomic, Owned, Pointer, Shared};
-pub(crate) struct Node<T> {
-    pub(crate) val: T,
-    children: Box<[Atomic<Node<T>>]>,
-    child_count: AtomicUsize,
-    in_use: AtomicBool,
-    terminal: AtomicBool,
+/// The smallest table of hashed `Children`, tables are always a power of two.
+const HASHED_CAPACITY: usize = 4;
+
+/// Tag set on a slot once its array is being copied into a bigger one,
+/// after that no thread may store into the slot again.
+pub(crate) const FROZEN: usize = 1;
+
+/// Tag set on the array pointer of `Children` by the one thread copying
+/// it under `ResizePolicy::Single`.
+const RESIZING: usize = 1;
+
+/// Tag set on the array pointer of `Children` that share their array
+/// with the `Children` that owns it, dropping them leaves the array be.
+const BORROWED: usize = 2;
+
+/// Tag set on a node's bucket pointer once its suffixes have been moved
+/// into child nodes, from then on the node only uses its children.
+const BURST: usize = 1;
+
+/// How the children arrays of every node of a trie are sized.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sizing {
+    /// The number of slots in a node's first array.
+    pub(crate) capacity: usize,
+    /// How many times bigger each new array is than the one it replaces.
+    pub(crate) growth: f64,
+    pub(crate) resize: ResizePolicy,
 }
 
-impl<T: fmt::Debug> fmt::Debug for Node<T> {
+impl Default for Sizing {
+    fn default() -> Self {
+        Self {
+            capacity: 4,
+            growth: 2.0,
+            resize: ResizePolicy::Cooperative,
+        }
+    }
+}
+
+impl Sizing {
+    /// The size of the array that replaces one of `len` slots.
+    fn grown(&self, len: usize) -> usize {
+        if len == 0 {
+            cmp::max(self.capacity, 1)
+        } else {
+            cmp::max((len as f64 * self.growth).ceil() as usize, len + 1)
+        }
+    }
+
+    fn grown_table(&self, len: usize) -> usize {
+        cmp::max(self.grown(len), HASHED_CAPACITY).next_power_of_two()
+    }
+}
+
+/// The length of the smallest hash table that holds `len` children
+/// without having to grow.
+fn table_len(len: usize) -> usize {
+    cmp::max(len + len / 3 + 1, HASHED_CAPACITY).next_power_of_two()
+}
+
+/// The memory taken up by `len` slots of a children array.
+fn slot_bytes<T>(len: usize) -> usize {
+    len * mem::size_of::<Atomic<Node<T>>>()
+}
+
+/// The memory taken up by a children array of `len` slots.
+fn array_bytes<T>(len: usize) -> usize {
+    mem::size_of::<Slots<T>>() + slot_bytes::<T>(len)
+}
+
+/// The memory taken up by a bucket with room for `capacity` suffixes.
+fn bucket_bytes<T>(capacity: usize) -> usize {
+    mem::size_of::<Bucket<T>>() + capacity * mem::size_of::<Atomic<Box<[T]>>>()
+}
+
+/// The memory taken up by one suffix of a bucket.
+fn suffix_bytes<T>(len: usize) -> usize {
+    mem::size_of::<Box<[T]>>() + len * mem::size_of::<T>()
+}
+
+/// `Box::new` that returns an error when the allocator fails instead of
+/// aborting.
+pub(crate) fn try_box<T>(val: T) -> Result<Box<T>, Error> {
+    let layout = alloc::Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(Box::new(val));
+    }
+    unsafe {
+        let ptr = alloc::alloc(layout) as *mut T;
+        if ptr.is_null() {
+            return Err(Error::OutOfMemory);
+        }
+        ptr.write(val);
+        Ok(Box::from_raw(ptr))
+    }
+}
+
+/// An empty `Vec` with room for exactly `len` items, an error when the
+/// allocator fails.
+pub(crate) fn try_vec<T>(len: usize) -> Result<Vec<T>, Error> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(len).map_err(|_| Error::OutOfMemory)?;
+    Ok(vec)
+}
+
+/// Collects the elements of an edge's tail, an error when the allocator
+/// fails instead of aborting.
+pub(crate) fn try_tail<T, I>(vals: I) -> Result<Box<[T]>, Error>
+where
+    I: ExactSizeIterator<Item = T>,
+{
+    let mut tail = try_vec(vals.len())?;
+    tail.extend(vals);
+    Ok(tail.into_boxed_slice())
+}
+
+/// Freezes every slot of an array that is about to be copied, so nothing
+/// can be added to it any more, and returns the nodes it holds. Shared by
+/// `Children` and the nodes of a `ByteTrie`.
+pub(crate) fn freeze<'g, N>(slots: &'g [Atomic<N>], g: &'g Guard) -> impl Iterator<Item = Shared<'g, N>> + Clone + 'g {
+    for slot in slots {
+        slot.fetch_or(FROZEN, SeqCst, g);
+    }
+    slots
+        .iter()
+        .map(move |slot| slot.load(SeqCst, g).with_tag(0))
+        .filter(|node| !node.is_null())
+}
+
+/// Charges `heap` for an array of `bytes` and makes it with `make`, the
+/// charge is taken back if that fails. Shared by `Children` and the nodes
+/// of a `ByteTrie`.
+pub(crate) fn alloc_array<A, F>(bytes: usize, heap: &Heap, make: F) -> Result<Box<A>, Error>
+where
+    F: FnOnce() -> Result<A, Error>,
+{
+    heap.charge(bytes)?;
+    make().and_then(try_box).inspect_err(|_| heap.refund(bytes))
+}
+
+/// Swaps `new`, made by `alloc_array`, in for the array `old` that `at`
+/// holds. If another thread got there first `new` is thrown away, either
+/// way the array that is gone is refunded to `heap` by what `bytes` says
+/// it took. The old array is freed once no reader can still be in it, the
+/// nodes it held have to be in `new` by then. Returns true if `new` was
+/// swapped in.
+pub(crate) fn replace_array<'g, A, B>(
+    at: &Atomic<A>,
+    old: Shared<'g, A>,
+    new: Box<A>,
+    bytes: B,
+    heap: &Heap,
+    g: &'g Guard,
+) -> bool
+where
+    B: Fn(&A) -> usize,
+{
+    let new_bytes = bytes(&new);
+    let installed = at.compare_and_set(old, Owned::from(new), SeqCst, g).is_ok();
+    if !installed {
+        heap.refund(new_bytes);
+    } else if let Some(array) = unsafe { old.as_ref() } {
+        heap.refund(bytes(array));
+        // only the array is freed the nodes now live in the new one
+        unsafe { pointers::defer_destroy(g, old.with_tag(0)) };
+    }
+    installed
+}
+
+/// The bytes allocated by a trie and the most it may allocate.
+///
+/// Every allocation is charged before it is made so that racing inserts
+/// can't together go over the budget, and refunded once it is freed or
+/// turns out not to be needed.
+#[derive(Debug)]
+pub(crate) struct Heap {
+    used: AtomicUsize,
+    budget: Option<usize>,
+}
+
+impl Heap {
+    pub(crate) fn new(budget: Option<usize>) -> Heap {
+        Self {
+            used: AtomicUsize::new(0),
+            budget,
+        }
+    }
+
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(SeqCst)
+    }
+
+    pub(crate) fn budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    /// Counts `bytes` about to be allocated, an error if that would take
+    /// the trie over its budget.
+    pub(crate) fn charge(&self, bytes: usize) -> Result<(), Error> {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => {
+                self.used.fetch_add(bytes, SeqCst);
+                return Ok(());
+            }
+        };
+        self.used
+            .fetch_update(SeqCst, SeqCst, |used| used.checked_add(bytes).filter(|&u| u <= budget))
+            .map(|_| ())
+            .map_err(|_| Error::OverBudget { budget })
+    }
+
+    /// An error if `bytes` more would take the trie over its budget, for
+    /// an allocation that is only charged once it is made.
+    pub(crate) fn check(&self, bytes: usize) -> Result<(), Error> {
+        match self.budget {
+            Some(budget) if self.used().saturating_add(bytes) > budget => Err(Error::OverBudget { budget }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts `bytes` whatever the budget, for an allocation that takes
+    /// the place of a larger one so removing keys never fails on it.
+    pub(crate) fn charge_over(&self, bytes: usize) {
+        self.used.fetch_add(bytes, SeqCst);
+    }
+
+    pub(crate) fn refund(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, SeqCst);
+    }
+}
+
+/// How the children of a `Slots` are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    /// Filled front to back, the first null slot is the end of the children.
+    Dense,
+    /// Slot `i` holds the child with alphabet index `i`.
+    Indexed,
+    /// An open addressing table probed from the hash of the value.
+    Hashed,
+}
+
+/// How `Children::rebuilt` lays out the children it is given, following
+/// the trie's layout.
+pub(crate) enum Place<'a, T> {
+    /// Front to back, in the order of the function if there is one.
+    Dense(Option<fn(&T, &T) -> Ordering>),
+    /// At the alphabet index of each value, in an array of the given size.
+    Indexed(Box<dyn Fn(&T) -> usize + 'a>, usize),
+    /// In a hash table.
+    Hashed(Box<dyn Fn(&T) -> u64 + 'a>),
+    /// In a hash table if the array being rebuilt is one, front to back
+    /// otherwise.
+    Adaptive(Box<dyn Fn(&T) -> u64 + 'a>),
+}
+
+/// The backing array of `Children`.
+struct Slots<T> {
+    nodes: Box<[Atomic<Node<T>>]>,
+    shape: Shape,
+}
+
+impl<T> Slots<T> {
+    fn new(len: usize, shape: Shape) -> Result<Slots<T>, Error> {
+        let mut nodes = try_vec(len)?;
+        nodes.resize_with(len, Atomic::null);
+        Ok(Self { nodes: nodes.into_boxed_slice(), shape })
+    }
+
+    /// Stores `node` in the first empty slot probed from `hash`, only for
+    /// a table nobody else can see yet.
+    fn store_hashed(&self, node: Shared<'_, Node<T>>, hash: u64, g: &Guard) {
+        let mask = self.nodes.len() - 1;
+        let mut idx = hash as usize & mask;
+        while !self.nodes[idx].load(SeqCst, g).is_null() {
+            idx = (idx + 1) & mask;
+        }
+        self.nodes[idx].store(node, SeqCst);
+    }
+}
+
+/// A growable lock-free array of child nodes.
+///
+/// Adding a child is a single CAS into the first empty slot, since every
+/// thread racing for the same value has to CAS that same slot no value
+/// is ever added twice. When the array is full it is frozen slot by slot,
+/// copied into a bigger array and the new array is swapped in, any thread
+/// that runs into a frozen slot helps finish the copy before retrying.
+pub(crate) struct Children<T> {
+    slots: Atomic<Slots<T>>,
+    count: AtomicUsize,
+}
+
+impl<T: fmt::Debug> fmt::Debug for Children<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let g = epoch::pin();
-        let len = self.child_count.load(SeqCst);
-        let mut v = Vec::default();
-        for x in self.children.iter() {
-            let node = x.load(SeqCst, &g);
+        f.debug_list().entries(self.iter(&g)).finish()
+    }
+}
+
+/// Frees every node below, one level at a time from a stack of its own so
+/// a deep trie can't overflow the call stack.
+impl<T> Drop for Children<T> {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_nodes(&mut stack);
+        while let Some(mut node) = stack.pop() {
+            node.children.take_nodes(&mut stack);
+        }
+    }
+}
+
+impl<T> Children<T> {
+    /// The array itself is not allocated until the first child is added.
+    pub(crate) fn new() -> Children<T> {
+        Self {
+            slots: Atomic::null(),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Children with an array of `capacity` slots made up front, a hash
+    /// table if `hashed`. The array is charged to `heap` whatever its budget.
+    pub(crate) fn with_capacity(capacity: usize, hashed: bool, heap: &Heap) -> Result<Children<T>, Error> {
+        let (len, shape) = if hashed {
+            (table_len(capacity), Shape::Hashed)
+        } else {
+            (capacity, Shape::Dense)
+        };
+        let slots = Slots::new(len, shape).and_then(try_box)?;
+        heap.charge_over(array_bytes::<T>(len));
+        Ok(Self {
+            slots: Atomic::from(slots),
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    /// The memory taken up by the array, not counting the children.
+    pub(crate) fn heap_size(&self, g: &Guard) -> usize {
+        match unsafe { self.slots.load(SeqCst, g).as_ref() } {
+            Some(slots) => array_bytes::<T>(slots.nodes.len()),
+            None => 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.count.load(SeqCst)
+    }
+
+    pub(crate) fn iter<'g>(&self, g: &'g Guard) -> impl Iterator<Item = &'g Node<T>>
+    where
+        T: 'g,
+    {
+        let slots = self.slots.load(SeqCst, g);
+        let (nodes, dense): (&'g [Atomic<Node<T>>], bool) = match unsafe { slots.as_ref() } {
+            Some(slots) => (&slots.nodes, slots.shape == Shape::Dense),
+            None => (&[], true),
+        };
+        nodes
+            .iter()
+            .map(move |n| n.load(SeqCst, g))
+            .take_while(move |n| !dense || !n.is_null())
+            .filter_map(|n| unsafe { n.as_ref() })
+    }
+
+    /// The child at alphabet index `idx` of an indexed array.
+    pub(crate) fn get<'g>(&self, idx: usize, g: &'g Guard) -> Option<&'g Node<T>>
+    where
+        T: 'g,
+    {
+        let slots = unsafe { self.slots.load(SeqCst, g).as_ref()? };
+        unsafe { slots.nodes.get(idx)?.load(SeqCst, g).as_ref() }
+    }
+
+    /// Adds `node` at alphabet index `idx` unless that slot is already taken,
+    /// either way the child in the slot is returned. The array is made with
+    /// `size` slots the first time and never grows.
+    pub(crate) fn add_at<'g>(
+        &self,
+        idx: usize,
+        size: usize,
+        node: Owned<Node<T>>,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Error>
+    where
+        T: 'g,
+    {
+        let mut slots = self.slots.load(SeqCst, g);
+        if slots.is_null() {
+            heap.charge(array_bytes::<T>(size))?;
+            let new = Slots::new(size, Shape::Indexed).and_then(try_box).inspect_err(|_| heap.refund(array_bytes::<T>(size)))?;
+            slots = match self.slots.compare_and_set(Shared::null(), Owned::from(new), SeqCst, g) {
+                Ok(slots) => slots,
+                Err(err) => {
+                    heap.refund(array_bytes::<T>(size));
+                    err.current
+                }
+            };
+        }
+
+        let slot = unsafe { &slots.deref().nodes[idx] };
+        match slot.compare_and_set(Shared::null(), node, SeqCst, g) {
+            Ok(added) => {
+                self.count.fetch_add(1, SeqCst);
+                Ok(unsafe { added.deref() })
+            }
+            Err(err) => Ok(unsafe { err.current.deref() }),
+        }
+    }
+
+    /// Scans for `val`, works whatever the shape of the array.
+    pub(crate) fn find<'g, Q>(&self, val: &Q, g: &'g Guard) -> Option<&'g Node<T>>
+    where
+        T: Borrow<Q> + 'g,
+        Q: ?Sized + Eq,
+    {
+        self.iter(g).find(|n| n.val.borrow() == val)
+    }
+
+    /// Adds `node` unless a child with the same value is already present,
+    /// either way the child that ends up in the array is returned.
+    pub(crate) fn add<'g>(&self, mut new: Owned<Node<T>>, sizing: &Sizing, heap: &Heap, g: &'g Guard) -> Result<&'g Node<T>, Error>
+    where
+        T: PartialEq + 'g,
+    {
+        loop {
+            let slots = self.slots.load(SeqCst, g);
+            match self.place(slots, new, g) {
+                Ok(node) => return Ok(node),
+                Err(back) => new = back,
+            }
+            self.grow(slots, sizing, heap, g)?;
+        }
+    }
+
+    /// Puts `new` into the first empty slot of a dense array, or finds the
+    /// child already holding its value. `new` is handed back if the array
+    /// is full or frozen.
+    fn place<'g>(
+        &self,
+        slots: Shared<'g, Slots<T>>,
+        mut new: Owned<Node<T>>,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Owned<Node<T>>>
+    where
+        T: PartialEq + 'g,
+    {
+        let nodes: &[Atomic<Node<T>>] = match unsafe { slots.as_ref() } {
+            Some(slots) => &slots.nodes,
+            None => &[],
+        };
+
+        let mut idx = 0;
+        while let Some(slot) = nodes.get(idx) {
+            let current = slot.load(SeqCst, g);
+            if current.tag() == FROZEN {
+                break;
+            }
+            if current.is_null() {
+                match slot.compare_and_set(Shared::null(), new, SeqCst, g) {
+                    Ok(added) => {
+                        self.count.fetch_add(1, SeqCst);
+                        return Ok(unsafe { added.deref() });
+                    }
+                    // somebody beat us to this slot, look at what they put there
+                    Err(err) => {
+                        new = err.new;
+                        continue;
+                    }
+                }
+            }
+            let current = unsafe { current.deref() };
+            if current.val == new.val {
+                return Ok(current);
+            }
+            idx += 1;
+        }
+        Err(new)
+    }
+
+    /// Binary searches an array kept sorted by `cmp`.
+    pub(crate) fn find_sorted<'g>(&self, val: &T, cmp: fn(&T, &T) -> Ordering, g: &'g Guard) -> Option<&'g Node<T>>
+    where
+        T: 'g,
+    {
+        let slots = unsafe { self.slots.load(SeqCst, g).as_ref()? };
+        let nodes = &slots.nodes;
+        let idx = nodes
+            .binary_search_by(|n| cmp(unsafe { &n.load(SeqCst, g).deref().val }, val))
+            .ok()?;
+        Some(unsafe { nodes[idx].load(SeqCst, g).deref() })
+    }
+
+    /// Adds `node` to an array kept sorted by `cmp` unless a child with
+    /// the same value is already present, either way the child that ends
+    /// up in the array is returned.
+    ///
+    /// Sorted arrays are never written to in place, every add copies the
+    /// array with the new child in its spot and swaps the copy in. Readers
+    /// binary search whichever copy they loaded.
+    pub(crate) fn add_sorted<'g>(
+        &self,
+        new: Owned<Node<T>>,
+        cmp: fn(&T, &T) -> Ordering,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Error>
+    where
+        T: 'g,
+    {
+        let new = new.into_shared(g);
+        let val = unsafe { &new.deref().val };
+        loop {
+            let slots = self.slots.load(SeqCst, g);
+            let nodes: &[Atomic<Node<T>>] = match unsafe { slots.as_ref() } {
+                Some(slots) => &slots.nodes,
+                None => &[],
+            };
+            let idx = match nodes.binary_search_by(|n| cmp(unsafe { &n.load(SeqCst, g).deref().val }, val)) {
+                Ok(idx) => {
+                    // `new` was never seen by anyone else
+                    drop(unsafe { new.into_owned() });
+                    return Ok(unsafe { nodes[idx].load(SeqCst, g).deref() });
+                }
+                Err(idx) => idx,
+            };
+
+            let bytes = array_bytes::<T>(nodes.len() + 1);
+            let copy = heap.charge(bytes).and_then(|()| {
+                let mut copy = try_vec(nodes.len() + 1)?;
+                copy.extend(nodes[..idx].iter().map(|n| Atomic::from(n.load(SeqCst, g))));
+                copy.push(Atomic::from(new));
+                copy.extend(nodes[idx..].iter().map(|n| Atomic::from(n.load(SeqCst, g))));
+                try_box(Slots { nodes: copy.into_boxed_slice(), shape: Shape::Dense }).inspect_err(|_| heap.refund(bytes))
+            });
+            let copy = match copy {
+                Ok(copy) => Owned::from(copy),
+                Err(e) => {
+                    drop(unsafe { new.into_owned() });
+                    return Err(e);
+                }
+            };
+            if self.slots.compare_and_set(slots, copy, SeqCst, g).is_ok() {
+                self.count.fetch_add(1, SeqCst);
+                if !slots.is_null() {
+                    heap.refund(array_bytes::<T>(nodes.len()));
+                    unsafe { pointers::defer_destroy(g, slots) };
+                }
+                return Ok(unsafe { new.deref() });
+            }
+            heap.refund(bytes);
+        }
+    }
+
+    /// Looks `val` up in a hash table, probing from `hash` until an empty slot.
+    pub(crate) fn find_hashed<'g, Q>(&self, val: &Q, hash: u64, g: &'g Guard) -> Option<&'g Node<T>>
+    where
+        T: Borrow<Q> + 'g,
+        Q: ?Sized + PartialEq,
+    {
+        let slots = unsafe { self.slots.load(SeqCst, g).as_ref()? };
+        let mask = slots.nodes.len() - 1;
+        let start = hash as usize & mask;
+        for i in 0..slots.nodes.len() {
+            // a frozen table still holds everything that was added to it
+            let node = unsafe { slots.nodes[(start + i) & mask].load(SeqCst, g).as_ref()? };
+            if node.val.borrow() == val {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// Adds `node` to a hash table unless a child with the same value is
+    /// already present, either way the child that ends up in the table is
+    /// returned.
+    ///
+    /// Like `add` a value only ever goes into the first empty slot of its
+    /// probe sequence so racing adds of the same value meet at the same slot.
+    /// Once the table is three quarters full it is frozen and rehashed into
+    /// a bigger one, lookups keep reading the old table until the new one
+    /// is swapped in.
+    pub(crate) fn add_hashed<'g, H>(
+        &self,
+        new: Owned<Node<T>>,
+        hash: H,
+        sizing: &Sizing,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Error>
+    where
+        T: PartialEq + 'g,
+        H: Fn(&T) -> u64,
+    {
+        self.insert_hashed(new, &hash, sizing, heap, g)
+    }
+
+    fn insert_hashed<'g, H>(
+        &self,
+        mut new: Owned<Node<T>>,
+        hash: &H,
+        sizing: &Sizing,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Error>
+    where
+        T: PartialEq + 'g,
+        H: Fn(&T) -> u64,
+    {
+        let h = hash(&new.val) as usize;
+        'retry: loop {
+            let slots = self.slots.load(SeqCst, g);
+            let nodes: &[Atomic<Node<T>>] = match unsafe { slots.as_ref() } {
+                Some(slots) => &slots.nodes,
+                None => &[],
+            };
+            if (self.len() + 1) * 4 > nodes.len() * 3 {
+                self.grow_hashed(slots, hash, sizing, heap, g)?;
+                continue;
+            }
+
+            let mask = nodes.len() - 1;
+            let mut i = 0;
+            while i < nodes.len() {
+                let slot = &nodes[(h + i) & mask];
+                let current = slot.load(SeqCst, g);
+                if current.tag() == FROZEN {
+                    self.grow_hashed(slots, hash, sizing, heap, g)?;
+                    continue 'retry;
+                }
+                if current.is_null() {
+                    match slot.compare_and_set(Shared::null(), new, SeqCst, g) {
+                        Ok(added) => {
+                            self.count.fetch_add(1, SeqCst);
+                            return Ok(unsafe { added.deref() });
+                        }
+                        // look at what won the slot before moving on
+                        Err(err) => {
+                            new = err.new;
+                            continue;
+                        }
+                    }
+                }
+                let current = unsafe { current.deref() };
+                if current.val == new.val {
+                    return Ok(current);
+                }
+                i += 1;
+            }
+            self.grow_hashed(slots, hash, sizing, heap, g)?;
+        }
+    }
+
+    /// Looks `val` up the way the current array is laid out, children start
+    /// out dense and turn into a hash table once there are enough of them.
+    pub(crate) fn find_adaptive<'g, Q, H>(&self, val: &Q, hash: H, g: &'g Guard) -> Option<&'g Node<T>>
+    where
+        T: Borrow<Q> + 'g,
+        Q: ?Sized + Eq,
+        H: FnOnce(&Q) -> u64,
+    {
+        let slots = unsafe { self.slots.load(SeqCst, g).as_ref()? };
+        match slots.shape {
+            Shape::Hashed => self.find_hashed(val, hash(val), g),
+            // if the array is rehashed before we get to it the scan still works
+            _ => self.find(val, g),
+        }
+    }
+
+    /// Adds `node` to a dense array until growing it would make it longer
+    /// than `limit`, from then on to a hash table.
+    pub(crate) fn add_adaptive<'g, H>(
+        &self,
+        mut new: Owned<Node<T>>,
+        hash: H,
+        limit: usize,
+        sizing: &Sizing,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<&'g Node<T>, Error>
+    where
+        T: PartialEq + 'g,
+        H: Fn(&T) -> u64,
+    {
+        loop {
+            let slots = self.slots.load(SeqCst, g);
+            let len = match unsafe { slots.as_ref() } {
+                Some(slots) if slots.shape == Shape::Hashed => {
+                    return self.insert_hashed(new, &hash, sizing, heap, g);
+                }
+                Some(slots) => slots.nodes.len(),
+                None => 0,
+            };
+            match self.place(slots, new, g) {
+                Ok(node) => return Ok(node),
+                Err(back) => new = back,
+            }
+            if sizing.grown(len) > limit {
+                self.grow_hashed(slots, &hash, sizing, heap, g)?;
+            } else {
+                self.grow(slots, sizing, heap, g)?;
+            }
+        }
+    }
+
+    /// Swaps `new` into the slot holding `old`, `old` is freed once no
+    /// reader can still be looking at it.
+    ///
+    /// Only safe while no other thread can add to or grow this array, the
+    /// trie's structure lock has to be held exclusively.
+    pub(crate) fn replace<'g>(&self, old: &Node<T>, new: Owned<Node<T>>, g: &'g Guard) -> &'g Node<T>
+    where
+        T: 'g,
+    {
+        let slots = unsafe { self.slots.load(SeqCst, g).deref() };
+        let slot = slots
+            .nodes
+            .iter()
+            .find(|slot| ptr::eq(slot.load(SeqCst, g).as_raw(), old))
+            .expect("replaced node is a child");
+        let new = new.into_shared(g);
+        let old = slot.swap(new, SeqCst, g);
+        unsafe {
+            pointers::defer_destroy(g, old);
+            new.deref()
+        }
+    }
+
+    /// Unlinks every child in one swap, a reader sees all of them or none.
+    /// They are freed once no reader can still be looking at them, until
+    /// then they can still be walked through what is returned.
+    ///
+    /// Only safe while the trie's structure lock is held exclusively.
+    pub(crate) fn unlink_all<'g>(&self, g: &'g Guard) -> Option<&'g Children<T>> {
+        let old = self.slots.swap(Shared::null(), SeqCst, g);
+        let count = self.count.swap(0, SeqCst);
+        if old.is_null() || old.tag() & BORROWED != 0 {
+            return None;
+        }
+        let unlinked = Children {
+            slots: Atomic::from(old),
+            count: AtomicUsize::new(count),
+        };
+        let unlinked = Owned::new(unlinked).into_shared(g);
+        unsafe {
+            pointers::defer_destroy(g, unlinked);
+            unlinked.as_ref()
+        }
+    }
+
+    /// A copy of this array without `gone` and with `added`, laid out by
+    /// `place`, for a copy of the node that owns it. The two arrays hold
+    /// the very same nodes, once the copy is in the trie the original has
+    /// to be let go with `retire` and a copy that never makes it in with
+    /// `release`, or the nodes would be freed twice.
+    pub(crate) fn rebuilt<'g>(
+        &self,
+        gone: &[&Node<T>],
+        added: &[Shared<'g, Node<T>>],
+        place: &Place<'_, T>,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<Children<T>, Error>
+    where
+        T: 'g,
+    {
+        let mut nodes = self
+            .iter(g)
+            .filter(|n| !gone.iter().any(|gone| ptr::eq(*gone, *n)))
+            .map(|n| Shared::from(n as *const Node<T>))
+            .chain(added.iter().copied())
+            .collect::<Vec<_>>();
+        if nodes.is_empty() {
+            return Ok(Children::new());
+        }
+        let hashed = unsafe { self.slots.load(SeqCst, g).as_ref() }.is_some_and(|slots| slots.shape == Shape::Hashed);
+        let copy = match place {
+            Place::Dense(_) => Self::alloc_slots(nodes.len(), Shape::Dense, heap)?,
+            Place::Indexed(_, size) => Self::alloc_slots(*size, Shape::Indexed, heap)?,
+            Place::Adaptive(_) if !hashed => Self::alloc_slots(nodes.len(), Shape::Dense, heap)?,
+            Place::Hashed(_) | Place::Adaptive(_) => Self::alloc_slots(table_len(nodes.len()), Shape::Hashed, heap)?,
+        };
+        // nobody else can see `copy` yet so plain stores are enough
+        match (copy.shape, place) {
+            (Shape::Indexed, Place::Indexed(index, _)) => {
+                for node in &nodes {
+                    copy.nodes[index(unsafe { &node.deref().val })].store(*node, SeqCst);
+                }
+            }
+            (Shape::Hashed, Place::Hashed(hash) | Place::Adaptive(hash)) => {
+                for node in &nodes {
+                    copy.store_hashed(*node, hash(unsafe { &node.deref().val }), g);
+                }
+            }
+            _ => {
+                if let Place::Dense(Some(cmp)) = place {
+                    nodes.sort_by(|a, b| cmp(unsafe { &a.deref().val }, unsafe { &b.deref().val }));
+                }
+                for (slot, node) in copy.nodes.iter().zip(&nodes) {
+                    slot.store(*node, SeqCst);
+                }
+            }
+        }
+        Ok(Self {
+            slots: Atomic::from(copy),
+            count: AtomicUsize::new(nodes.len()),
+        })
+    }
+
+    /// Swaps in the array of `new`, made from this one by `rebuilt`, so a
+    /// reader sees every old child or every new one. The old array is
+    /// freed once no reader can still be looking at it, its nodes live on
+    /// in the new one.
+    ///
+    /// Only safe while the trie's structure lock is held exclusively.
+    pub(crate) fn swap_array(&self, new: Children<T>, heap: &Heap, g: &Guard) {
+        let slots = new.slots.swap(Shared::null(), SeqCst, g);
+        self.count.store(new.len(), SeqCst);
+        let old = self.slots.swap(slots, SeqCst, g);
+        if let Some(old_slots) = unsafe { old.as_ref() } {
+            if old.tag() & BORROWED == 0 {
+                heap.refund(array_bytes::<T>(old_slots.nodes.len()));
+                unsafe { pointers::defer_destroy(g, old) };
+            }
+        }
+    }
+
+    /// Leaves the nodes of this array to the copy `rebuilt` made of it that
+    /// took its place, the array itself is freed once no reader can still
+    /// be looking at it.
+    pub(crate) fn retire(&self, heap: &Heap, g: &Guard) {
+        let old = self.slots.fetch_or(BORROWED, SeqCst, g);
+        if let Some(old_slots) = unsafe { old.as_ref() } {
+            if old.tag() & BORROWED == 0 {
+                heap.refund(array_bytes::<T>(old_slots.nodes.len()));
+                unsafe { pointers::defer_destroy(g, old) };
+            }
+        }
+    }
+
+    /// Frees the array of a copy made by `rebuilt` that never made it into
+    /// the trie, its nodes are left to the original.
+    pub(crate) fn release(&self, heap: &Heap) {
+        // nobody else ever saw the copy
+        let g = unsafe { epoch::unprotected() };
+        let slots = self.slots.swap(Shared::null(), Relaxed, g);
+        self.count.store(0, Relaxed);
+        if let Some(old_slots) = unsafe { slots.as_ref() } {
+            if slots.tag() & BORROWED == 0 {
+                heap.refund(array_bytes::<T>(old_slots.nodes.len()));
+                // only the array is freed
+                drop(unsafe { slots.into_owned() });
+            }
+        }
+    }
+
+    /// A second handle to the same array, the two must never both be
+    /// grown or added to. Used to move the children of a node that is
+    /// being replaced to its replacement, the array stays owned by `self`
+    /// until it is handed over.
+    pub(crate) fn share(&self, g: &Guard) -> Children<T> {
+        Self {
+            slots: Atomic::from(self.slots.load(SeqCst, g).with_tag(BORROWED)),
+            count: AtomicUsize::new(self.len()),
+        }
+    }
+
+    /// Makes `to`, made by `share`, the owner of the array instead of
+    /// `self`.
+    pub(crate) fn hand_over(&self, to: &Children<T>, g: &Guard) {
+        to.slots.fetch_and(!BORROWED, SeqCst, g);
+        self.slots.fetch_or(BORROWED, SeqCst, g);
+    }
+
+    /// Moves the nodes of an array this handle owns onto `stack` and frees
+    /// the array, the handle is left empty.
+    fn take_nodes(&mut self, stack: &mut Vec<Owned<Node<T>>>) {
+        // `&mut self` means no other thread can be looking
+        let g = unsafe { epoch::unprotected() };
+        let slots = self.slots.swap(Shared::null(), Relaxed, g);
+        if slots.is_null() || slots.tag() & BORROWED != 0 {
+            return;
+        }
+        let slots = unsafe { slots.with_tag(0).into_owned() };
+        for slot in slots.nodes.iter() {
+            let node = slot.load(Relaxed, g).with_tag(0);
             if !node.is_null() {
-                v.push(unsafe { node.deref() });
+                stack.push(unsafe { node.into_owned() });
+            }
+        }
+    }
+
+    /// Decides who copies `old`. Returns the pointer the copy has to be
+    /// swapped in against, or `None` if another thread is copying it and
+    /// has already swapped its copy in by the time this returns.
+    fn claim<'g>(&self, old: Shared<'g, Slots<T>>, sizing: &Sizing, g: &'g Guard) -> Option<Shared<'g, Slots<T>>> {
+        if sizing.resize == ResizePolicy::Cooperative {
+            return Some(old);
+        }
+        let claimed = old.with_tag(RESIZING);
+        if old.tag() != RESIZING && self.slots.compare_and_set(old, claimed, SeqCst, g).is_ok() {
+            return Some(claimed);
+        }
+        while self.slots.load(SeqCst, g) == claimed {
+            thread::yield_now();
+        }
+        None
+    }
+
+    /// Swaps `new` in for `old` as returned by `claim`, if another thread
+    /// already swapped in a copy ours is thrown away. Returns true if ours
+    /// was swapped in, either way the array that is gone is refunded.
+    fn install<'g>(&self, old: Shared<'g, Slots<T>>, new: Box<Slots<T>>, heap: &Heap, g: &'g Guard) -> bool {
+        let bytes = |slots: &Slots<T>| array_bytes::<T>(slots.nodes.len());
+        replace_array(&self.slots, old, new, bytes, heap, g)
+    }
+
+    /// Charges `heap` for an array of `len` slots and allocates it.
+    fn alloc_slots(len: usize, shape: Shape, heap: &Heap) -> Result<Box<Slots<T>>, Error> {
+        alloc_array(array_bytes::<T>(len), heap, || Slots::new(len, shape))
+    }
+
+    /// Freezes every slot of `old` and puts its children into a dense
+    /// array of `cap` slots, or as many as it takes to hold them, then
+    /// swaps that in. Returns the length of the copy if it was swapped in.
+    fn copy_dense<'g>(
+        &self,
+        old: Shared<'g, Slots<T>>,
+        cap: usize,
+        sizing: &Sizing,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<Option<usize>, Error>
+    where
+        T: 'g,
+    {
+        let old = match self.claim(old, sizing, g) {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let nodes: &[Atomic<Node<T>>] = match unsafe { old.as_ref() } {
+            Some(slots) => &slots.nodes,
+            None => &[],
+        };
+        let live = freeze(nodes, g);
+
+        let cap = cmp::max(cap, live.clone().count());
+        let copy = match Self::alloc_slots(cap, Shape::Dense, heap) {
+            Ok(copy) => copy,
+            Err(e) => {
+                self.abandon(old, g);
+                return Err(e);
+            }
+        };
+        for (slot, node) in copy.nodes.iter().zip(live) {
+            // nobody else can see `copy` yet so plain stores are enough
+            slot.store(node, SeqCst);
+        }
+        Ok(if self.install(old, copy, heap, g) { Some(cap) } else { None })
+    }
+
+    /// Freezes every slot of `old` and rehashes its children into a table
+    /// of `cap` slots, or big enough to hold them, then swaps that in the
+    /// same way `copy_dense` does.
+    fn copy_hashed<'g, H>(
+        &self,
+        old: Shared<'g, Slots<T>>,
+        cap: usize,
+        hash: &H,
+        sizing: &Sizing,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<Option<usize>, Error>
+    where
+        T: 'g,
+        H: Fn(&T) -> u64,
+    {
+        let old = match self.claim(old, sizing, g) {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let nodes: &[Atomic<Node<T>>] = match unsafe { old.as_ref() } {
+            Some(slots) => &slots.nodes,
+            None => &[],
+        };
+        let live = freeze(nodes, g);
+
+        let cap = cmp::max(cap.next_power_of_two(), table_len(live.clone().count()));
+        let copy = match Self::alloc_slots(cap, Shape::Hashed, heap) {
+            Ok(copy) => copy,
+            Err(e) => {
+                self.abandon(old, g);
+                return Err(e);
             }
+        };
+        for node in live {
+            copy.store_hashed(node, hash(&unsafe { node.deref() }.val), g);
+        }
+        Ok(if self.install(old, copy, heap, g) { Some(cap) } else { None })
+    }
+
+    /// Gives up on copying `old` after its slots were frozen. The array
+    /// stays frozen so every add to it tries to copy it again, failing
+    /// until there is memory for the copy.
+    fn abandon<'g>(&self, old: Shared<'g, Slots<T>>, g: &'g Guard) {
+        // under `ResizePolicy::Single` the other threads wait on the tag
+        if old.tag() == RESIZING {
+            let _ = self.slots.compare_and_set(old, old.with_tag(0), SeqCst, g);
         }
+    }
+
+    /// Copies `old` into a bigger dense array.
+    fn grow<'g>(&self, old: Shared<'g, Slots<T>>, sizing: &Sizing, heap: &Heap, g: &'g Guard) -> Result<(), Error>
+    where
+        T: 'g,
+    {
+        let len = unsafe { old.as_ref() }.map_or(0, |slots| slots.nodes.len());
+        self.copy_dense(old, sizing.grown(len), sizing, heap, g).map(|_| ())
+    }
+
+    /// Rehashes `old` into a bigger table.
+    fn grow_hashed<'g, H>(
+        &self,
+        old: Shared<'g, Slots<T>>,
+        hash: &H,
+        sizing: &Sizing,
+        heap: &Heap,
+        g: &'g Guard,
+    ) -> Result<(), Error>
+    where
+        T: 'g,
+        H: Fn(&T) -> u64,
+    {
+        let len = unsafe { old.as_ref() }.map_or(0, |slots| slots.nodes.len());
+        self.copy_hashed(old, sizing.grown_table(len), hash, sizing, heap, g).map(|_| ())
+    }
 
+    /// Makes room in a dense array for `additional` more children.
+    pub(crate) fn reserve(&self, additional: usize, sizing: &Sizing, heap: &Heap, g: &Guard) -> Result<(), Error> {
+        let slots = self.slots.load(SeqCst, g);
+        let len = match unsafe { slots.as_ref() } {
+            Some(slots) if slots.shape == Shape::Dense => slots.nodes.len(),
+            Some(_) => return Ok(()),
+            None => 0,
+        };
+        let want = self.len() + additional;
+        if want > len {
+            self.copy_dense(slots, want, sizing, heap, g)?;
+        }
+        Ok(())
+    }
+
+    /// Makes room in a hash table for `additional` more children, a dense
+    /// array is rehashed into one.
+    pub(crate) fn reserve_hashed<H>(&self, additional: usize, hash: H, sizing: &Sizing, heap: &Heap, g: &Guard) -> Result<(), Error>
+    where
+        H: Fn(&T) -> u64,
+    {
+        let slots = self.slots.load(SeqCst, g);
+        let want = table_len(self.len() + additional);
+        match unsafe { slots.as_ref() } {
+            Some(slots) if slots.shape == Shape::Indexed => return Ok(()),
+            Some(slots) if slots.shape == Shape::Hashed && want <= slots.nodes.len() => return Ok(()),
+            _ => {}
+        }
+        self.copy_hashed(slots, want, &hash, sizing, heap, g).map(|_| ())
+    }
+
+    /// Copies a dense array that has room to spare into one just long
+    /// enough for its children. Unless `all` only arrays less than half
+    /// full are copied. Returns the number of bytes freed.
+    pub(crate) fn shrink(&self, all: bool, sizing: &Sizing, heap: &Heap, g: &Guard) -> usize {
+        let slots = self.slots.load(SeqCst, g);
+        let len = match unsafe { slots.as_ref() } {
+            Some(slots) if slots.shape == Shape::Dense => slots.nodes.len(),
+            _ => return 0,
+        };
+        let used = self.len();
+        if used >= len || (!all && used * 2 >= len) {
+            return 0;
+        }
+        match self.copy_dense(slots, used, sizing, heap, g) {
+            Ok(Some(copied)) => slot_bytes::<T>(len - copied),
+            _ => 0,
+        }
+    }
+
+    /// Like `shrink` for a hash table, unless `all` only tables less than
+    /// a quarter full are rehashed. Dense arrays are left to `shrink`.
+    pub(crate) fn shrink_hashed<H>(&self, all: bool, hash: H, sizing: &Sizing, heap: &Heap, g: &Guard) -> usize
+    where
+        H: Fn(&T) -> u64,
+    {
+        let slots = self.slots.load(SeqCst, g);
+        let len = match unsafe { slots.as_ref() } {
+            Some(slots) if slots.shape == Shape::Hashed => slots.nodes.len(),
+            Some(slots) if slots.shape == Shape::Dense => return self.shrink(all, sizing, heap, g),
+            _ => return 0,
+        };
+        let used = self.len();
+        if table_len(used) >= len || (!all && used * 4 >= len) {
+            return 0;
+        }
+        match self.copy_hashed(slots, table_len(used), &hash, sizing, heap, g) {
+            Ok(Some(copied)) => slot_bytes::<T>(len.saturating_sub(copied)),
+            _ => 0,
+        }
+    }
+}
+
+/// Suffixes kept flat below a node of a burst trie, filled front to back
+/// like `Children` so the same suffix is never added twice.
+pub(crate) struct Bucket<T> {
+    suffixes: Box<[Atomic<Box<[T]>>]>,
+}
+
+impl<T> Bucket<T> {
+    /// The memory taken up by the bucket and its suffixes.
+    pub(crate) fn heap_size(&self, g: &Guard) -> usize {
+        bucket_bytes::<T>(self.suffixes.len()) + self.iter(g).map(|s| suffix_bytes::<T>(s.len())).sum::<usize>()
+    }
+
+    pub(crate) fn iter<'g>(&'g self, g: &'g Guard) -> impl Iterator<Item = &'g [T]> {
+        self.suffixes
+            .iter()
+            .map(move |s| s.load(SeqCst, g))
+            .take_while(|s| !s.is_null())
+            .map(|s| unsafe { &**s.deref() })
+    }
+
+    /// The suffixes that continue past `prefix`.
+    pub(crate) fn below<'g>(&'g self, prefix: &'g [T], g: &'g Guard) -> impl Iterator<Item = &'g [T]>
+    where
+        T: PartialEq,
+    {
+        self.iter(g)
+            .filter(move |s| s.len() > prefix.len() && s.starts_with(prefix))
+    }
+}
+
+impl<T> Drop for Bucket<T> {
+    fn drop(&mut self) {
+        let g = unsafe { epoch::unprotected() };
+        for slot in self.suffixes.iter() {
+            let suffix = slot.load(Relaxed, g);
+            if !suffix.is_null() {
+                drop(unsafe { suffix.into_owned() });
+            }
+        }
+    }
+}
+
+/// What became of a suffix handed to `Node::add_suffix`.
+pub(crate) enum AddSuffix {
+    Added,
+    Present,
+    /// The bucket has to burst before anything else can go in.
+    Full,
+    /// The node has burst, the suffix belongs under its children.
+    Burst,
+}
+
+pub(crate) struct Node<T> {
+    pub(crate) val: T,
+    /// The rest of the edge after `val` in a compressed trie, always empty
+    /// otherwise. Only the last element of the edge can be terminal.
+    tail: Box<[T]>,
+    children: Children<T>,
+    /// Suffixes below this node in a burst trie, null until the first one.
+    bucket: Atomic<Bucket<T>>,
+    terminal: AtomicBool,
+    /// The number of keys ending at or below this node.
+    count: AtomicUsize,
+}
+
+/// The children are freed by `Children` itself.
+impl<T> Drop for Node<T> {
+    fn drop(&mut self) {
+        let g = unsafe { epoch::unprotected() };
+        let bucket = self.bucket.swap(Shared::null(), Relaxed, g).with_tag(0);
+        if !bucket.is_null() {
+            drop(unsafe { bucket.into_owned() });
+        }
+    }
+}
+
+/// Only the values of the children are shown, a whole subtree could be
+/// too deep to print.
+impl<T: fmt::Debug> fmt::Debug for Node<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = epoch::pin();
         f.debug_struct("Node")
             .field("value", &self.val)
-            .field("child_count", &len)
-            .field("terminal", &self.terminal.load(SeqCst))
-            .field("children", &v)
+            .field("tail", &self.tail)
+            .field("child_count", &self.child_len())
+            .field("terminal", &self.is_terminal())
+            .field("count", &self.count())
+            .field("children", &self.children.iter(&g).map(|n| &n.val).collect::<Vec<_>>())
             .finish()
     }
 }
 
-impl<T: Clone> Node<T> {
-    pub(crate) fn to_value(&self) -> T {
-        self.val.clone()
+impl<T> Node<T> {
+    /// The part of this node's edge from `tail[at]` on, taking over its
+    /// children, terminal flag and count. The node itself is left as it
+    /// was for readers that still hold it.
+    pub(crate) fn split_off(&self, at: usize, copy: fn(&T) -> T, g: &Guard) -> Result<Node<T>, Error> {
+        Ok(Self {
+            val: copy(&self.tail[at]),
+            tail: try_tail(self.tail[at + 1..].iter().map(copy))?,
+            children: self.children.share(g),
+            bucket: Atomic::null(),
+            terminal: AtomicBool::new(self.is_terminal()),
+            count: AtomicUsize::new(self.count()),
+        })
     }
 }
 
-impl<T: Eq + fmt::Debug> Node<T> {
-    pub(crate) fn new(val: T, terminal: bool) -> Node<T> {
-        Self {
-            val,
-            children: vec![Atomic::null(); 26 / 2].into_boxed_slice(),
-            child_count: AtomicUsize::new(0),
-            in_use: AtomicBool::default(),
-            terminal: AtomicBool::new(terminal),
+impl<T: PartialEq> Node<T> {
+    /// Adds `suffix` to this node's bucket, making the bucket with room for
+    /// `capacity` suffixes if there is none yet, copying it with `copy`.
+    ///
+    /// Suffixes are only added while the trie's structure lock is held
+    /// shared and buckets only burst while it is held exclusively.
+    pub(crate) fn add_suffix<E: key::Element<T>>(
+        &self,
+        suffix: &[E],
+        capacity: usize,
+        copy: fn(&T) -> T,
+        heap: &Heap,
+        g: &Guard,
+    ) -> Result<AddSuffix, Error> {
+        let mut bucket = self.bucket.load(SeqCst, g);
+        if bucket.tag() == BURST {
+            return Ok(AddSuffix::Burst);
+        }
+        if bucket.is_null() {
+            let bytes = bucket_bytes::<T>(capacity);
+            heap.charge(bytes)?;
+            let new = try_vec(capacity).and_then(|mut slots| {
+                slots.resize_with(capacity, Atomic::null);
+                try_box(Bucket { suffixes: slots.into_boxed_slice() })
+            });
+            let new = new.inspect_err(|_| heap.refund(bytes))?;
+            bucket = match self.bucket.compare_and_set(Shared::null(), Owned::from(new), SeqCst, g) {
+                Ok(bucket) => bucket,
+                Err(err) => {
+                    heap.refund(bytes);
+                    err.current
+                }
+            };
+        }
+
+        let bucket = unsafe { bucket.deref() };
+        let mut new = None;
+        let mut idx = 0;
+        while let Some(slot) = bucket.suffixes.get(idx) {
+            match unsafe { slot.load(SeqCst, g).as_ref() } {
+                Some(current) => {
+                    if current.len() == suffix.len() && current.iter().zip(suffix).all(|(a, b)| a == key::Element::get(b)) {
+                        if new.is_some() {
+                            heap.refund(suffix_bytes::<T>(suffix.len()));
+                        }
+                        return Ok(AddSuffix::Present);
+                    }
+                    idx += 1;
+                }
+                None => {
+                    let owned = match new.take() {
+                        Some(owned) => owned,
+                        None => Owned::from(Self::alloc_suffix(suffix, copy, heap)?),
+                    };
+                    match slot.compare_and_set(Shared::null(), owned, SeqCst, g) {
+                        Ok(_) => return Ok(AddSuffix::Added),
+                        // look at what won the slot before moving on
+                        Err(err) => new = Some(err.new),
+                    }
+                }
+            }
+        }
+        if new.is_some() {
+            heap.refund(suffix_bytes::<T>(suffix.len()));
         }
+        Ok(AddSuffix::Full)
+    }
+
+    /// Charges `heap` for `suffix` and copies it out of the key.
+    fn alloc_suffix<E: key::Element<T>>(suffix: &[E], copy: fn(&T) -> T, heap: &Heap) -> Result<Box<Box<[T]>>, Error> {
+        let bytes = suffix_bytes::<T>(suffix.len());
+        heap.charge(bytes)?;
+        let owned = try_vec(suffix.len()).and_then(|mut owned| {
+            owned.extend(suffix.iter().map(|e| copy(key::Element::get(e))));
+            try_box(owned.into_boxed_slice())
+        });
+        owned.inspect_err(|_| heap.refund(bytes))
+    }
+}
+
+impl<T> Node<T> {
+    pub(crate) fn new(val: T) -> Node<T> {
+        Self::with_tail(val, Box::new([]))
     }
 
-    /// TODO using `MaybeUninit` correctly??
-    pub(crate) fn null() -> Node<T> {
-        #[allow(clippy::uninit_assumed_init)]
+    pub(crate) fn with_tail(val: T, tail: Box<[T]>) -> Node<T> {
         Self {
-            val: unsafe { MaybeUninit::uninit().assume_init() },
-            children: vec![Atomic::null(); 26 / 2].into_boxed_slice(),
-            child_count: AtomicUsize::new(0),
-            in_use: AtomicBool::default(),
+            val,
+            tail,
+            children: Children::new(),
+            bucket: Atomic::null(),
             terminal: AtomicBool::default(),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// A node that will take the place of one whose edge was split,
+    /// starting out with the count of the node it replaces.
+    pub(crate) fn with_count(val: T, tail: Box<[T]>, count: usize) -> Node<T> {
+        let node = Self::with_tail(val, tail);
+        node.count.store(count, SeqCst);
+        node
+    }
+
+    pub(crate) fn tail(&self) -> &[T] {
+        &self.tail
+    }
+
+    /// The bucket of suffixes below this node, `None` once it has burst or
+    /// if it never had one.
+    pub(crate) fn suffixes<'g>(&self, g: &'g Guard) -> Option<&'g Bucket<T>> {
+        unsafe { self.bucket.load(SeqCst, g).as_ref() }
+    }
+
+    /// Marks the node as burst, its suffixes must already be in child
+    /// nodes. Only while holding the trie's structure lock exclusively.
+    pub(crate) fn end_bucket(&self, heap: &Heap, g: &Guard) {
+        if let Some(bucket) = self.suffixes(g) {
+            heap.refund(bucket.heap_size(g));
+        }
+        let old = self.bucket.swap(Shared::null().with_tag(BURST), SeqCst, g);
+        if !old.is_null() {
+            unsafe { pointers::defer_destroy(g, old.with_tag(0)) };
         }
     }
 
-    pub(crate) fn as_value(&self) -> &T {
-        &self.val
+    /// The memory taken up by the node itself and its edge, not counting
+    /// its children or bucket.
+    pub(crate) fn heap_size(&self) -> usize {
+        Self::size_with_tail(self.tail.len())
+    }
+
+    /// The memory a node with a tail of `len` elements takes up, like
+    /// `heap_size`.
+    pub(crate) fn size_with_tail(len: usize) -> usize {
+        mem::size_of::<Node<T>>() + len * mem::size_of::<T>()
+    }
+
+    pub(crate) fn children(&self) -> &Children<T> {
+        &self.children
     }
 
     pub(crate) fn child_len(&self) -> usize {
-        self.child_count.load(SeqCst)
+        self.children.len()
     }
 
     pub(crate) fn is_terminal(&self) -> bool {
         self.terminal.load(SeqCst)
     }
 
-    pub(crate) fn children_iter<'a>(&'a self, g: &'a Guard) -> Vec<&Atomic<Node<T>>> {
-        self.children.iter().filter(|n| !n.load(SeqCst, &g).is_null()).collect()
+    /// Marks this node as the end of a key, returns true if it was not already.
+    pub(crate) fn set_terminal(&self) -> bool {
+        !self.terminal.swap(true, SeqCst)
     }
 
-    pub(crate) fn get_child(&self, idx: usize) -> Option<&Atomic<Node<T>>> {
-        self.children.get(idx)
+    /// Stops this node being the end of a key, returns true if it was.
+    pub(crate) fn unset_terminal(&self) -> bool {
+        self.terminal.swap(false, SeqCst)
     }
 
-    pub(crate) fn child_position(&self, other: &Node<T>, g: &Guard) -> Option<usize> {
-        self.children
-            .iter()
-            .position(|node| unsafe {
-                let n = node.load(SeqCst, g);
-                if n.is_null() {
-                    false
-                } else {
-                    n.deref().as_value() == &other.val
-                }
-            })
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(SeqCst)
     }
 
-    pub(crate) fn find_node(&self, other: &T, g: &Guard) -> Option<&Atomic<Node<T>>> {
-        self.children
-            .iter()
-            .find(|node| unsafe { 
-                let n = node.load(SeqCst, g);
-                if n.is_null() {
-                    false
-                } else {
-                    n.deref().as_value() == other
-                }
-             })
+    /// Called on every node along the path of a newly inserted key.
+    pub(crate) fn count_key(&self) {
+        self.count.fetch_add(1, SeqCst);
     }
 
-    pub(crate) fn last_child<'g>(&self, g: &'g Guard) -> Option<Shared<'g, Node<T>>> {
-        self.children.get(self.child_len() - 1).map(|n| n.load(SeqCst, g))
+    /// Called on every node above a subtree of `keys` keys that is removed.
+    pub(crate) fn uncount_keys(&self, keys: usize) {
+        self.count.fetch_sub(keys, SeqCst);
     }
 
-    pub(crate) fn add_child<'g>(&self, node: Node<T>, g: &'g Guard) -> Option<Shared<'g, Node<T>>> {
-        let len = self.child_len();
-        if len >= self.children.len() {
-            todo!("resize Node.children")
-        }
-        // check for match if true keep recursing down
-        if let Some(idx) = self.child_position(&node, g) {
-            return self.children.get(idx).map(|n| n.load(SeqCst, g));
-        }
-
-        if let Some(n) = self.children.get(len) {
-            let new = Owned::from(node);
-            // TODO deal with failure
-            assert!(n.compare_and_set(Shared::null(), new, SeqCst, g).is_ok());
-            assert!(self.child_count.fetch_add(1, SeqCst) == len);
-        };
-        self.last_child(g)
+    pub(crate) fn children_iter<'g>(&self, g: &'g Guard) -> impl Iterator<Item = &'g Node<T>>
+    where
+        T: 'g,
+    {
+        self.children.iter(g)
     }
 }
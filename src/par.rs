@@ -0,0 +1,180 @@
+//! Rayon support, behind the `rayon` feature.
+
+use crossbeam_epoch as epoch;
+use rayon::iter::{FromParallelIterator, IntoParallelIterator, ParallelExtend, ParallelIterator};
+
+use crate::{ParTrie, TrieKey};
+
+/// A part of a parallel walk over the trie.
+enum Split<T> {
+    /// Only the key itself, its children are split up on their own.
+    Key(Vec<T>),
+    /// Every key that starts with the prefix, walked by one thread.
+    Below(Vec<T>),
+}
+
+impl<T, K> ParallelExtend<K> for ParTrie<T>
+where
+    T: Eq + Send + Sync,
+    K: TrieKey<T> + Send,
+{
+    /// Inserts every key, each thread rayon hands keys to pins once.
+    fn par_extend<I>(&mut self, keys: I)
+    where
+        I: IntoParallelIterator<Item = K>,
+    {
+        let trie = &*self;
+        keys.into_par_iter().for_each_init(
+            || trie.pin(),
+            |pinned, key| {
+                pinned.insert(key);
+            },
+        );
+    }
+}
+
+impl<T, K> FromParallelIterator<K> for ParTrie<T>
+where
+    T: Eq + Send + Sync,
+    K: TrieKey<T> + Send,
+{
+    fn from_par_iter<I>(keys: I) -> Self
+    where
+        I: IntoParallelIterator<Item = K>,
+    {
+        let mut trie = ParTrie::new();
+        trie.par_extend(keys);
+        trie
+    }
+}
+
+impl<T> ParTrie<T>
+where
+    T: Clone + Eq + Send + Sync,
+{
+    /// Every key in the trie, in the order `find` returns them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    /// use rayon::prelude::*;
+    ///
+    /// let words = vec!["cat", "car", "cow", "dog"];
+    /// let trie = words.par_iter().copied().collect::<ParTrie<char>>();
+    /// assert_eq!(trie.par_iter().count(), 4);
+    /// assert_eq!(trie.par_iter_prefix("ca").count(), 2);
+    /// ```
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = Vec<T>> + '_ {
+        self.par_iter_prefix(Vec::new())
+    }
+
+    /// Every key that starts with `prefix`, `prefix` included, in the order
+    /// `find` returns them.
+    ///
+    /// The subtree is split up front: any child with more keys below it
+    /// than a few times the number of threads can share is split into its
+    /// own children, the rest are each walked by one thread.
+    pub fn par_iter_prefix<K: TrieKey<T>>(&self, prefix: K) -> impl ParallelIterator<Item = Vec<T>> + '_ {
+        self.split(prefix.elements().collect())
+            .into_par_iter()
+            .flat_map_iter(move |split| match split {
+                Split::Key(key) => vec![key],
+                Split::Below(prefix) => self.find(prefix).into_iter().collect(),
+            })
+    }
+
+    /// Folds every key that starts with `prefix` into an `A`, each part of
+    /// the subtree starting from `identity` and the parts combined with
+    /// `reduce`. The subtree is split up like it is for `par_iter_prefix`
+    /// and the parts are handed out through rayon's work stealing.
+    ///
+    /// Inserts can carry on while the fold runs, every key that was in the
+    /// trie before the fold started is folded exactly once and keys that
+    /// are inserted while it runs may or may not be. Without the feature
+    /// `par_fold` takes the same arguments and folds on the calling thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::new();
+    /// for word in "the quick brown fox jumps over the lazy dog".split(' ') {
+    ///     trie.insert(word);
+    /// }
+    /// let chars = trie.par_fold("", || 0, |sum, key| sum + key.len(), |a, b| a + b);
+    /// assert_eq!(chars, 32);
+    ///
+    /// let mut lengths = trie.par_fold(
+    ///     "",
+    ///     Vec::new,
+    ///     |mut lengths, key| {
+    ///         lengths.push(key.len());
+    ///         lengths
+    ///     },
+    ///     |mut a, b| {
+    ///         a.extend(b);
+    ///         a
+    ///     },
+    /// );
+    /// lengths.sort_unstable();
+    /// assert_eq!(lengths, vec![3, 3, 3, 4, 4, 5, 5, 5]);
+    /// ```
+    pub fn par_fold<K, A, I, F, R>(&self, prefix: K, identity: I, fold: F, reduce: R) -> A
+    where
+        K: TrieKey<T>,
+        A: Send,
+        I: Fn() -> A + Sync + Send,
+        F: Fn(A, &[T]) -> A + Sync + Send,
+        R: Fn(A, A) -> A + Sync + Send,
+    {
+        self.split(prefix.elements().collect())
+            .into_par_iter()
+            .map(|split| match split {
+                Split::Key(key) => fold(identity(), &key),
+                Split::Below(prefix) => self.find(prefix).into_iter().fold(identity(), |acc, key| fold(acc, &key)),
+            })
+            .reduce(&identity, &reduce)
+    }
+
+    /// Breaks the subtree below `prefix` up into parts small enough to be
+    /// walked by one thread each, in the order `find` walks them. Every
+    /// node above the parts is visited once, its children are stepped to
+    /// from where it was found.
+    fn split(&self, prefix: Vec<T>) -> Vec<Split<T>> {
+        let (raw, g) = (&self.raw, &epoch::pin());
+        let mut child = |c, val: &T| raw.child(c, val, g);
+        let level = match raw.descend(prefix.iter(), &mut child, g) {
+            Some(level) => level,
+            None => return vec![],
+        };
+        let mut next = Some(level.next(g));
+        let total = next.iter().flatten().map(|(_, count)| count).sum::<usize>();
+        let grain = (total / (rayon::current_num_threads() * 4)).max(1);
+
+        let mut splits = vec![];
+        // each child pushed with the number of keys below it
+        let mut stack = vec![(prefix, total + 1, level)];
+        while let Some((prefix, count, level)) = stack.pop() {
+            if count <= grain {
+                splits.push(Split::Below(prefix));
+                continue;
+            }
+            if level.is_terminal(g) {
+                splits.push(Split::Key(prefix.clone()));
+            }
+            // the prefix's own children were already read for the total
+            let children = next.take().unwrap_or_else(|| level.next(g));
+            // pushed back to front so the first child comes out first
+            for (val, count) in children.into_iter().rev() {
+                if let Some((level, _)) = raw.step(level, val, &mut child, g) {
+                    let mut prefix = prefix.clone();
+                    prefix.push(val.clone());
+                    stack.push((prefix, count, level));
+                }
+            }
+        }
+        splits
+    }
+}
@@ -0,0 +1,210 @@
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::{fmt, mem};
+
+use crossbeam_epoch::{self as epoch, Guard};
+
+use crate::{batch_order, CursorRef, Error, Found, RawTrie, TrieKey};
+
+/// A `ParTrie` pinned to the current thread's epoch, made by `ParTrie::pin`.
+///
+/// Every method runs under the one guard the handle holds, so a batch of
+/// lookups pins once instead of once per call. Nothing in the trie is freed
+/// while the handle lives, which is what lets lookups hand out elements
+/// borrowed from the trie instead of cloning them. The flip side is that
+/// memory replaced by other threads is not freed either, so a handle should
+/// not be kept around for longer than a batch.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let trie = ParTrie::new();
+/// let pinned = trie.pin();
+/// pinned.insert("cat");
+/// pinned.insert("cow");
+///
+/// assert!(pinned.contains("cat") && !pinned.contains("co"));
+/// assert_eq!(pinned.next_elements("c"), vec![(&'a', 1), (&'o', 1)]);
+/// assert_eq!(pinned.find("ca").as_collected(), vec![&[&'c', &'a', &'t']]);
+/// ```
+pub struct TrieRef<'t, T> {
+    trie: &'t RawTrie<T>,
+    guard: Guard,
+}
+
+impl<'t, T: fmt::Debug> fmt::Debug for TrieRef<'t, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrieRef").field("trie", &self.trie).finish()
+    }
+}
+
+impl<'t, T> TrieRef<'t, T> {
+    pub(crate) fn new(trie: &'t RawTrie<T>) -> TrieRef<'t, T> {
+        Self {
+            trie,
+            guard: epoch::pin(),
+        }
+    }
+
+    /// The number of distinct first elements, the children of the root.
+    pub fn len(&self) -> usize {
+        self.trie.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trie.is_empty()
+    }
+}
+
+impl<'t, T: Eq> TrieRef<'t, T> {
+    /// A cursor at the root that borrows this handle's pin, see
+    /// `CursorRef`.
+    pub fn cursor(&self) -> CursorRef<'_, T> {
+        CursorRef::new(self.trie, &self.guard)
+    }
+
+    /// Like `ParTrie::insert`.
+    pub fn insert<K: TrieKey<T>>(&self, key: K) -> bool {
+        self.try_insert(key).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `ParTrie::try_insert`.
+    pub fn try_insert<K: TrieKey<T>>(&self, key: K) -> Result<bool, Error> {
+        self.trie.insert_seq(key.elements(), &self.guard)
+    }
+
+    /// Like `ParTrie::contains`.
+    pub fn contains<K: TrieKey<T>>(&self, key: K) -> bool {
+        let g = &self.guard;
+        self.trie.contains(key.elements(), |c, val| self.trie.child(c, val, g), g)
+    }
+
+    /// Like `ParTrie::contains_slice`.
+    pub fn contains_slice<Q>(&self, key: &[&Q]) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        let g = &self.guard;
+        self.trie.contains(key.iter().copied(), |c, val| self.trie.child_borrowed(c, val, g), g)
+    }
+
+    /// Like `ParTrie::next_elements` but the elements are borrowed from
+    /// the trie.
+    pub fn next_elements<K: TrieKey<T>>(&self, prefix: K) -> Vec<(&T, usize)> {
+        self.trie.next_elements(prefix.elements(), &self.guard)
+    }
+
+    /// Like `ParTrie::find` but the keys are made of elements borrowed from
+    /// the trie.
+    pub fn find<K: TrieKey<T>>(&self, prefix: K) -> Found<&T> {
+        let mut found = Found::new();
+        self.find_into(prefix, &mut found);
+        found
+    }
+
+    /// Like `find` but fills `found`, reusing what it allocated for
+    /// earlier searches. Whatever `found` held before is cleared.
+    pub fn find_into<'r, K: TrieKey<T>>(&'r self, prefix: K, found: &mut Found<&'r T>) {
+        let g = &self.guard;
+        self.trie.find(prefix.elements(), found, |c, val| self.trie.child(c, val, g), g)
+    }
+
+    /// Like `ParTrie::find_slice` but the keys are made of elements
+    /// borrowed from the trie.
+    pub fn find_slice<Q>(&self, prefix: &[&Q]) -> Found<&T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        let g = &self.guard;
+        let mut found = Found::new();
+        self.trie.find(prefix.iter().copied(), &mut found, |c, val| self.trie.child_borrowed(c, val, g), g);
+        found
+    }
+
+    /// Inserts every one of `keys`, returning whether each was added in the
+    /// order the keys were given. The keys are sorted first so in a plain
+    /// trie each common prefix is walked once instead of once per key.
+    /// A key given twice is added by the first of the two.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use par_trie::ParTrie;
+    ///
+    /// let trie = ParTrie::new();
+    /// let pinned = trie.pin();
+    /// assert_eq!(pinned.insert_batch(["cow", "cat", "cow", "car"]), vec![true, true, false, true]);
+    /// assert_eq!(pinned.contains_batch(["car", "ca", "cow"]), vec![true, false, true]);
+    ///
+    /// let found = pinned.find_batch(["cow", "ca", "dog"]);
+    /// assert_eq!(found.iter().map(|f| f.len()).collect::<Vec<_>>(), vec![1, 2, 0]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Like `ParTrie::insert`, once every other key is in.
+    pub fn insert_batch<K, I>(&self, keys: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        self.try_insert_batch(keys)
+            .into_iter()
+            .map(|added| added.unwrap_or_else(|e| panic!("{}", e)))
+            .collect()
+    }
+
+    /// Like `insert_batch` but returns an error for each rejected key
+    /// instead of panicking, nothing of a rejected key is added.
+    pub fn try_insert_batch<K, I>(&self, keys: I) -> Vec<Result<bool, Error>>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        let mut keys = keys.into_iter().map(|k| k.elements().collect()).collect::<Vec<_>>();
+        let order = batch_order(&keys);
+        let sorted = order.iter().map(|&idx| mem::take(&mut keys[idx])).collect::<Vec<_>>();
+        let mut added = vec![Ok(false); order.len()];
+        for (&idx, result) in order.iter().zip(self.trie.insert_batch(sorted.into_iter(), &self.guard)) {
+            added[idx] = result;
+        }
+        added
+    }
+
+    /// Whether each of `keys` was inserted, in the order the keys were
+    /// given. Like `insert_batch` the keys are sorted first so each common
+    /// prefix is walked once.
+    pub fn contains_batch<K, I>(&self, keys: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        let g = &self.guard;
+        let keys = keys.into_iter().map(|k| k.elements().collect()).collect::<Vec<_>>();
+        let mut contains = vec![false; keys.len()];
+        self.trie.descend_batch(&keys, &batch_order(&keys), g, |idx, level, _| {
+            contains[idx] = level.is_some_and(|level| level.is_terminal(g));
+        });
+        contains
+    }
+
+    /// Like `find` for each of `prefixes`, in the order they were given.
+    /// Like `insert_batch` the prefixes are sorted first so each common
+    /// prefix is walked once.
+    pub fn find_batch<K, I>(&self, prefixes: I) -> Vec<Found<&T>>
+    where
+        I: IntoIterator<Item = K>,
+        K: TrieKey<T>,
+        T: Ord,
+    {
+        let prefixes = prefixes.into_iter().map(|p| p.elements().collect()).collect::<Vec<_>>();
+        self.trie.find_batch(&prefixes, &batch_order(&prefixes), &self.guard)
+    }
+}
@@ -31,6 +31,19 @@
 //! IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 //! DEALINGS IN THE SOFTWARE.
 
+// kept as close to upstream as possible so lints are silenced instead of fixed
+#![allow(
+    dead_code,
+    unused_unsafe,
+    unexpected_cfgs,
+    clippy::cmp_null,
+    clippy::explicit_auto_deref,
+    clippy::extra_unused_lifetimes,
+    clippy::non_canonical_clone_impl,
+    clippy::non_canonical_partial_ord_impl,
+    clippy::redundant_field_names
+)]
+
 use std::borrow::{Borrow, BorrowMut};
 use std::cmp;
 use std::fmt;
@@ -584,6 +597,17 @@ impl<'g, T> Default for Shared<'g, T> {
     }
 }
 
+/// `Guard::defer_destroy` for this module's `Shared`, the pointee is dropped
+/// once no thread pinned at the time of the call can still be reading it.
+///
+/// # Safety
+///
+/// `ptr` must not be reachable by any thread that pins after this call.
+pub unsafe fn defer_destroy<T>(g: &Guard, ptr: Shared<'_, T>) {
+    let data = ptr.into_usize();
+    g.defer_unchecked(move || drop(Owned::<T>::from_usize(data)));
+}
+
 #[cfg(test)]
 mod tests {
     use super::Shared;
@@ -0,0 +1,384 @@
+//! A `ParTrie` as a set of keys, the standard collection traits and
+//! conversions to and from the std sets.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::iter::FromIterator;
+use std::ptr;
+
+use crossbeam_epoch::{self as epoch, Guard};
+
+use crate::node::Node;
+use crate::{Error, Level, ParTrie, RawTrie, TrieKey};
+
+impl<T: Eq, K: TrieKey<T>> Extend<K> for ParTrie<T> {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, keys: I) {
+        let pinned = self.pin();
+        for key in keys {
+            pinned.insert(key);
+        }
+    }
+}
+
+/// Collects keys of any type the trie can take.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let words: ParTrie<char> = vec!["code", "coder", "cookie"].into_iter().collect();
+/// let bytes: ParTrie<u8> = vec![&b"GET /"[..], b"POST /"].into_iter().collect();
+/// let tokens: ParTrie<u32> = vec![vec![1, 2], vec![1, 3]].into_iter().collect();
+/// assert!(words.contains("coder") && bytes.contains(b"POST /") && tokens.contains(vec![1, 3]));
+/// ```
+impl<T: Eq, K: TrieKey<T>> FromIterator<K> for ParTrie<T> {
+    fn from_iter<I: IntoIterator<Item = K>>(keys: I) -> Self {
+        let mut trie = ParTrie::new();
+        trie.extend(keys);
+        trie
+    }
+}
+
+/// A deep copy laid out, sized and limited like the original, with a budget
+/// of its own. Keys inserted while the trie is being cloned may or may not
+/// make it into the copy.
+///
+/// # Panics
+///
+/// If the copy runs out of its memory budget, which can happen when the
+/// original only just fit.
+impl<T: Clone + Eq> Clone for ParTrie<T> {
+    fn clone(&self) -> Self {
+        let g = epoch::pin();
+        let trie = ParTrie { raw: self.raw.empty_like() };
+        for key in self.pin().find(Vec::new()) {
+            trie.raw
+                .insert_seq(key.into_iter(), &g)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+        trie
+    }
+}
+
+/// Two tries are equal when they hold the same keys, however they are laid
+/// out and whatever order the keys went in.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let a = ParTrie::<char>::from_str_list(&["cat", "cow"]);
+/// let b = ParTrie::compressed();
+/// b.insert("cow");
+/// b.insert("cat");
+/// assert_eq!(a, b);
+/// ```
+impl<T: Eq> PartialEq for ParTrie<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let ours = self.pin();
+        let keys = ours.find(Vec::new());
+        let (theirs, g) = (&other.raw, &epoch::pin());
+        let child = |c, val: &T| theirs.child(c, val, g);
+        keys.len() == theirs.key_count(g) && keys.into_iter().all(|key| theirs.contains(key.into_iter(), child, g))
+    }
+}
+
+impl<T: Eq> Eq for ParTrie<T> {}
+
+/// Hashes the set of keys, equal tries hash the same whatever order their
+/// keys are kept in.
+impl<T: Hash + Eq> Hash for ParTrie<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let pinned = self.pin();
+        let keys = pinned.find(Vec::new());
+        state.write_usize(keys.len());
+        // every key hashed on its own with the same keys and summed, so the
+        // order they are found in does not matter
+        let sum = keys.into_iter().fold(0u64, |sum, key| {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            sum.wrapping_add(hasher.finish())
+        });
+        state.write_u64(sum);
+    }
+}
+
+/// Set operations, the result is laid out, sized and limited like `self`
+/// with a budget of its own.
+///
+/// The two tries are walked side by side so no keys are collected on the
+/// way, a subtree only one of them has is walked on its own or skipped
+/// when none of its keys can make it into the result. Keys inserted into
+/// either trie while the walk runs may or may not be seen.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let a = ParTrie::<char>::from_str_list(&["cat", "cow", "dog"]);
+/// let b = ParTrie::<char>::from_str_list(&["cow", "dog", "emu"]);
+///
+/// assert_eq!(a.union(&b), ParTrie::from_str_list(&["cat", "cow", "dog", "emu"]));
+/// assert_eq!(a.intersection(&b), ParTrie::from_str_list(&["cow", "dog"]));
+/// assert_eq!(a.difference(&b), ParTrie::from_str_list(&["cat"]));
+/// assert_eq!(a.symmetric_difference(&b), ParTrie::from_str_list(&["cat", "emu"]));
+///
+/// a.union_with(&b);
+/// assert!(a.contains("emu"));
+/// a.intersection_with(&ParTrie::from_str_list(&["cat", "emu", "yak"]));
+/// assert_eq!(a, ParTrie::from_str_list(&["cat", "emu"]));
+/// a.difference_with(&b);
+/// assert_eq!(a, ParTrie::from_str_list(&["cat"]));
+/// ```
+///
+/// # Panics
+///
+/// If the result runs out of its memory budget or is over one of its
+/// limits.
+impl<T: Clone + Eq> ParTrie<T> {
+    /// Every key in either trie.
+    pub fn union(&self, other: &ParTrie<T>) -> ParTrie<T> {
+        self.merged(other, |ours, theirs| ours || theirs)
+    }
+
+    /// Every key in both tries.
+    pub fn intersection(&self, other: &ParTrie<T>) -> ParTrie<T> {
+        self.merged(other, |ours, theirs| ours && theirs)
+    }
+
+    /// Every key in this trie but not in `other`.
+    pub fn difference(&self, other: &ParTrie<T>) -> ParTrie<T> {
+        self.merged(other, |ours, theirs| ours && !theirs)
+    }
+
+    /// Every key in exactly one of the tries.
+    pub fn symmetric_difference(&self, other: &ParTrie<T>) -> ParTrie<T> {
+        self.merged(other, |ours, theirs| ours != theirs)
+    }
+
+    /// Inserts every key of `other` this trie does not have yet, only
+    /// walking the parts of `other` that hold such keys.
+    pub fn union_with(&self, other: &ParTrie<T>) {
+        let g = epoch::pin();
+        self.raw
+            .merge(&other.raw, |ours, theirs| theirs && !ours, &self.raw, &g)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Removes every key `other` does not have, walking the two tries side
+    /// by side. A subtree `other` has no part of goes in one swap, the other
+    /// keys go one at a time so a reader can see some of them gone and
+    /// others not yet. Waits for running inserts like `remove_prefix`.
+    pub fn intersection_with(&self, other: &ParTrie<T>) {
+        let g = epoch::pin();
+        self.raw
+            .prune(&other.raw, |ours, theirs| ours && theirs, &g)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Removes every key `other` has like `intersection_with`, a subtree
+    /// `other` has no part of is not walked.
+    pub fn difference_with(&self, other: &ParTrie<T>) {
+        let g = epoch::pin();
+        self.raw
+            .prune(&other.raw, |ours, theirs| ours && !theirs, &g)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn merged(&self, other: &ParTrie<T>, keep: Keep) -> ParTrie<T> {
+        let g = epoch::pin();
+        let merged = ParTrie { raw: self.raw.empty_like() };
+        self.raw
+            .merge(&other.raw, keep, &merged.raw, &g)
+            .unwrap_or_else(|e| panic!("{}", e));
+        merged
+    }
+}
+
+/// Picks the keys of a set operation by whether they are in the first trie
+/// and in the second.
+type Keep = fn(bool, bool) -> bool;
+
+impl<T: Clone + Eq> RawTrie<T> {
+    /// Walks this trie and `other` side by side, inserting every key that
+    /// `keep` picks into `out`.
+    fn merge<'g>(&'g self, other: &'g RawTrie<T>, keep: Keep, out: &RawTrie<T>, g: &'g Guard) -> Result<(), Error> {
+        enum Visit<'g, T> {
+            /// The levels an element leads to in each trie, `None` where
+            /// that trie has no such prefix.
+            Enter(Option<Level<'g, T>>, Option<Level<'g, T>>, &'g T),
+            Leave,
+        }
+
+        let step = |trie: &'g RawTrie<T>, level, val: &T| {
+            let mut child = |c, val: &T| trie.child(c, val, g);
+            trie.step(level, val, &mut child, g).map(|(level, _)| level)
+        };
+        let mut path: Vec<&T> = vec![];
+        let mut stack = vec![];
+        let (mut ours, mut theirs) = (Some(Level::Root(self)), Some(Level::Root(other)));
+        loop {
+            let terminal = |level: Option<Level<'g, T>>| level.is_some_and(|level| level.is_terminal(g));
+            if keep(terminal(ours), terminal(theirs)) {
+                out.insert_seq(path.iter().copied(), g)?;
+            }
+
+            // pushed back to front so the first child is entered first
+            let start = stack.len();
+            if let Some(level) = ours {
+                for (val, _) in level.next(g) {
+                    let next = theirs.and_then(|theirs| step(other, theirs, val));
+                    // a subtree only we have is all keys of ours alone
+                    if next.is_some() || keep(true, false) {
+                        stack.push(Visit::Enter(step(self, level, val), next, val));
+                        stack.push(Visit::Leave);
+                    }
+                }
+            }
+            if let Some(level) = theirs.filter(|_| keep(false, true)) {
+                for (val, _) in level.next(g) {
+                    if ours.and_then(|ours| step(self, ours, val)).is_none() {
+                        stack.push(Visit::Enter(None, step(other, level, val), val));
+                        stack.push(Visit::Leave);
+                    }
+                }
+            }
+            stack[start..].reverse();
+
+            loop {
+                match stack.pop() {
+                    Some(Visit::Enter(next_ours, next_theirs, val)) => {
+                        path.push(val);
+                        ours = next_ours;
+                        theirs = next_theirs;
+                        break;
+                    }
+                    Some(Visit::Leave) => {
+                        path.pop();
+                    }
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl<T: Clone + Eq> RawTrie<T> {
+    /// Walks this trie and `other` side by side like `merge`, removing
+    /// every key of this trie that `keep` doesn't pick. A subtree of ours
+    /// that `other` has no part of is unlinked in one swap, or not walked
+    /// at all when `keep` picks keys only we have.
+    ///
+    /// Waits for every insert to finish like `remove_prefix`.
+    fn prune<'g>(&'g self, other: &'g RawTrie<T>, keep: Keep, g: &'g Guard) -> Result<(), Error> {
+        enum Visit<'g, T> {
+            /// The levels an element leads to in each trie, `other` has the
+            /// element when it is entered.
+            Enter(Level<'g, T>, Level<'g, T>),
+            /// Back out to a path of this many nodes.
+            Leave(usize),
+        }
+
+        let _exclusive = self.exclusive();
+        let step = |trie: &'g RawTrie<T>, level, val: &T| {
+            let mut child = |c, val: &T| trie.child(c, val, g);
+            trie.step(level, val, &mut child, g).map(|(level, _)| level)
+        };
+        let gone = |theirs: Option<Level<'g, T>>| !keep(true, theirs.is_some_and(|level| level.is_terminal(g)));
+        // the nodes walked through, what unlinking takes
+        let mut path: Vec<&Node<T>> = vec![];
+        let mut stack = vec![];
+        let (mut ours, mut theirs) = (Level::Root(self), Level::Root(other));
+        loop {
+            if ours.is_terminal(g) && gone(Some(theirs)) {
+                self.unset_key(&path, ours, g);
+            }
+            // pushed back to front so the first child is entered first
+            let start = stack.len();
+            let next = match ours {
+                Level::Bucket(node, bucket, _) => {
+                    // the suffixes are all looked up first and go in one copy
+                    let suffix = |s: &[T]| s.iter().try_fold(theirs, |level, val| step(other, level, val));
+                    self.remove_suffixes(&path, node, bucket, |s| gone(suffix(s)), g)?;
+                    vec![]
+                }
+                _ => ours.next(g),
+            };
+            for (val, _) in next {
+                let next = match step(self, ours, val) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                if let Some(next_theirs) = step(other, theirs, val) {
+                    stack.push(Visit::Enter(next, next_theirs));
+                    stack.push(Visit::Leave(path.len()));
+                } else if gone(None) {
+                    let node = next.node().expect("a step always leaves the root");
+                    let mut below = path.clone();
+                    if !below.last().is_some_and(|last| ptr::eq(*last, node)) {
+                        below.push(node);
+                    }
+                    self.unlink_keys(&below, node.count(), g)?;
+                }
+            }
+            stack[start..].reverse();
+
+            loop {
+                match stack.pop() {
+                    Some(Visit::Enter(next_ours, next_theirs)) => {
+                        let node = next_ours.node().expect("a step always leaves the root");
+                        if !path.last().is_some_and(|last| ptr::eq(*last, node)) {
+                            path.push(node);
+                        }
+                        ours = next_ours;
+                        theirs = next_theirs;
+                        break;
+                    }
+                    Some(Visit::Leave(len)) => path.truncate(len),
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl<T: Eq> From<BTreeSet<Vec<T>>> for ParTrie<T> {
+    fn from(keys: BTreeSet<Vec<T>>) -> Self {
+        keys.into_iter().collect()
+    }
+}
+
+impl<T: Eq, S: BuildHasher> From<HashSet<Vec<T>, S>> for ParTrie<T> {
+    fn from(keys: HashSet<Vec<T>, S>) -> Self {
+        keys.into_iter().collect()
+    }
+}
+
+/// Every key in the trie.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeSet;
+/// use par_trie::ParTrie;
+///
+/// let trie = ParTrie::<char>::from_str_list(&["cow", "cat"]);
+/// let keys = BTreeSet::from(&trie);
+/// assert_eq!(keys.into_iter().collect::<Vec<_>>(), vec![vec!['c', 'a', 't'], vec!['c', 'o', 'w']]);
+/// ```
+impl<T: Clone + Ord> From<&ParTrie<T>> for BTreeSet<Vec<T>> {
+    fn from(trie: &ParTrie<T>) -> Self {
+        trie.find(Vec::new()).into_iter().collect()
+    }
+}
+
+/// Every key in the trie.
+impl<T: Clone + Hash + Eq, S: BuildHasher + Default> From<&ParTrie<T>> for HashSet<Vec<T>, S> {
+    fn from(trie: &ParTrie<T>) -> Self {
+        trie.find(Vec::new()).into_iter().collect()
+    }
+}
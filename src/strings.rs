@@ -0,0 +1,64 @@
+use crate::ParTrie;
+
+/// Strings stored as sequences of `char`.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let trie: ParTrie<char> = vec!["code", "coder", "cookie"].into_iter().collect();
+/// assert!(trie.contains_str("coder"));
+///
+/// let mut words = trie.complete("cod").collect::<Vec<_>>();
+/// words.sort();
+/// assert_eq!(words, vec!["code", "coder"]);
+/// ```
+impl ParTrie<char> {
+    pub fn from_str_list(list: &[&str]) -> ParTrie<char> {
+        list.iter().copied().collect()
+    }
+
+    /// Inserts `s`, returns true if it was not already present.
+    pub fn insert_str(&self, s: &str) -> bool {
+        self.insert(s)
+    }
+
+    pub fn contains_str(&self, s: &str) -> bool {
+        self.contains(s)
+    }
+
+    /// Every string that starts with `prefix`, `prefix` included.
+    pub fn complete(&self, prefix: &str) -> impl Iterator<Item = String> {
+        self.find(prefix)
+            .into_iter()
+            .map(|chars| chars.into_iter().collect())
+    }
+}
+
+/// Byte strings stored as sequences of `u8`.
+///
+/// # Examples
+///
+/// ```
+/// use par_trie::ParTrie;
+///
+/// let trie: ParTrie<u8> = vec![&b"GET /"[..], b"GET /index", b"POST /"].into_iter().collect();
+/// assert!(trie.contains_bytes(b"POST /"));
+/// assert_eq!(trie.complete_bytes(b"GET").count(), 2);
+/// ```
+impl ParTrie<u8> {
+    /// Inserts `bytes`, returns true if it was not already present.
+    pub fn insert_bytes(&self, bytes: &[u8]) -> bool {
+        self.insert_slice(bytes)
+    }
+
+    pub fn contains_bytes(&self, bytes: &[u8]) -> bool {
+        self.contains(bytes)
+    }
+
+    /// Every byte string that starts with `prefix`, `prefix` included.
+    pub fn complete_bytes(&self, prefix: &[u8]) -> impl Iterator<Item = Vec<u8>> {
+        self.find(prefix).into_iter()
+    }
+}
@@ -0,0 +1,146 @@
+//! Checks that dropping a trie gives back every byte it allocated.
+//!
+//! The allocator counts for the whole process so everything runs in one
+//! test, one thread at a time. The epoch keeps its queue of put off frees
+//! in blocks of a couple of KiB that come and go on its own schedule, so
+//! each case runs many times over and is allowed a few of those blocks.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicIsize, Ordering::SeqCst};
+
+use crossbeam_epoch as epoch;
+use par_trie::{ByteTrie, Dna, ParTrie, ResizePolicy};
+
+struct Counting;
+
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size() as isize, SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size() as isize, SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// What the epoch itself may be holding on to at any one time.
+const EPOCH_SLACK: isize = 8 << 10;
+
+/// How many times each case runs, any real leak adds up to more than the
+/// slack long before this.
+const RUNS: usize = 100;
+
+/// Runs everything the epoch has put off, like arrays replaced by bigger
+/// ones and nodes replaced by an edge split.
+fn collect_garbage() {
+    for _ in 0..256 {
+        epoch::pin().flush();
+    }
+}
+
+/// Runs `f` over and over and checks nothing it allocated is left once
+/// its garbage is collected.
+fn assert_no_leaks<F: Fn()>(name: &str, f: F) {
+    collect_garbage();
+    let before = LIVE.load(SeqCst);
+    for _ in 0..RUNS {
+        f();
+    }
+    collect_garbage();
+    let leaked = LIVE.load(SeqCst) - before;
+    assert!(leaked < EPOCH_SLACK, "{} leaked {} bytes", name, leaked);
+}
+
+fn words() -> Vec<String> {
+    std::fs::read_to_string("data/1984.txt")
+        .unwrap()
+        .split_whitespace()
+        .take(1_000)
+        .map(String::from)
+        .collect()
+}
+
+/// Makes one of the kinds of trie under test.
+type Make = fn() -> ParTrie<char>;
+
+fn fill(trie: ParTrie<char>, words: &[String]) {
+    // a trie over its budget refuses some of them
+    for w in words {
+        let _ = trie.try_insert(w);
+    }
+    trie.insert("");
+    assert!(trie.contains(&words[0]));
+}
+
+#[test]
+fn dropping_frees_everything() {
+    let words = words();
+    // the first pin registers this thread with the epoch for good
+    collect_garbage();
+
+    let tries: Vec<(&str, Make)> = vec![
+        ("plain", ParTrie::new),
+        ("root capacity", || ParTrie::with_capacity(64)),
+        ("sorted", || ParTrie::builder().sorted().build()),
+        ("hashed", || ParTrie::builder().hashed().build()),
+        ("adaptive", || ParTrie::builder().adaptive(4).resize_policy(ResizePolicy::Single).build()),
+        ("compressed", ParTrie::compressed),
+        ("burst", || ParTrie::burst(4)),
+        ("budget", || ParTrie::builder().memory_budget(1 << 14).build()),
+    ];
+    for (name, make) in &tries {
+        assert_no_leaks(name, || fill(make(), &words));
+    }
+
+    assert_no_leaks("compacted", || {
+        let trie = ParTrie::builder().hashed().root_capacity(1000).build();
+        words.iter().for_each(|w| drop(trie.try_insert(w)));
+        trie.shrink_to_fit();
+    });
+    assert_no_leaks("removed", || {
+        let trie = ParTrie::burst(4);
+        words.iter().for_each(|w| drop(trie.try_insert(w)));
+        trie.remove_prefix("t");
+        trie.remove_prefix("");
+        trie.insert("sun");
+    });
+    assert_no_leaks("retained", || {
+        let trie = ParTrie::burst(4);
+        words.iter().for_each(|w| drop(trie.try_insert(w)));
+        trie.retain(|key| key.len() % 2 == 0);
+        assert!(trie.drain().count() > 0);
+        trie.insert("sun");
+        trie.clear();
+    });
+    assert_no_leaks("renamed", || {
+        let trie = ParTrie::compressed();
+        words.iter().for_each(|w| drop(trie.try_insert(w)));
+        assert!(trie.rename_prefix("th", "zq").unwrap() > 0);
+        assert!(trie.rename_prefix("zqe", "the").unwrap() > 0);
+        trie.remove_prefix("a");
+        let _ = trie.rename_prefix("s", "a");
+    });
+    assert_no_leaks("alphabet", || {
+        let trie = ParTrie::with_alphabet(Dna);
+        trie.insert("GATTACA");
+        assert!(trie.try_insert("GATXACA").is_err());
+    });
+    assert_no_leaks("deep", || {
+        let trie = ParTrie::new();
+        trie.insert("ab".repeat(5_000).as_str());
+    });
+    assert_no_leaks("byte", || {
+        let trie = ByteTrie::new();
+        for w in &words {
+            trie.insert(w.as_bytes());
+        }
+        trie.insert(&[7; 1_000]);
+    });
+}